@@ -1,7 +1,12 @@
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use core::mem::size_of;
 use core::ptr::{self, null_mut};
 use std::alloc::{alloc, dealloc, Layout};
+use std::borrow::Cow;
+use std::collections::BinaryHeap;
 use std::ffi::{c_char, CStr};
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
 
 #[repr(C)]
 pub struct HashResult {
@@ -69,12 +74,13 @@ fn dealloc_bytes(ptr: *mut u8) {
     }
 }
 
-#[no_mangle]
+// Only exported unmangled for wasm; on native targets these would shadow libc's allocator.
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
 pub extern "C" fn malloc(size: usize) -> *mut u8 {
     alloc_bytes(size)
 }
 
-#[no_mangle]
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
 pub extern "C" fn free(ptr: *mut u8) {
     dealloc_bytes(ptr)
 }
@@ -135,11 +141,155 @@ fn create_error_result(message: &str) -> *mut HashResult {
     create_hash_result(null_mut(), 1, error_message)
 }
 
-fn calculate_hash_for_rgba(
-    image_data: &[u8],
+/// Largest accepted `hash_size`; keeps the bit count (hash_size²) bounded.
+const MAX_HASH_SIZE: usize = 64;
+
+/// Single-channel image with floating point samples in the 0..=255 range.
+struct GrayImage {
     width: usize,
     height: usize,
-) -> Result<String, &'static str> {
+    pixels: Vec<f32>,
+}
+
+/// How the alpha channel takes part in hashing.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AlphaMode {
+    /// Use the stored colour of every pixel, even fully transparent ones.
+    Ignore = 0,
+    /// Composite onto the background colour before the grayscale conversion.
+    Composite = 1,
+    /// Composite, then append a hash of the alpha plane so shape differences count too.
+    Channel = 2,
+}
+
+impl AlphaMode {
+    const ALL: [AlphaMode; 3] = [AlphaMode::Ignore, AlphaMode::Composite, AlphaMode::Channel];
+
+    fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| *mode as i32 == id)
+    }
+
+    fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().as_bytes() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AlphaMode::Ignore => "ignore",
+            AlphaMode::Composite => "composite",
+            AlphaMode::Channel => "channel",
+        }
+    }
+}
+
+/// How RGB is weighted into the single brightness channel that gets hashed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LumaModel {
+    /// Unweighted `(r + g + b) / 3`, the historical behaviour.
+    Average = 0,
+    /// BT.601 weights on gamma-encoded values.
+    Bt601 = 1,
+    /// BT.709 weights on gamma-encoded values.
+    Bt709 = 2,
+    /// BT.709 weights on linear light after sRGB decoding, scaled back to 0..=255.
+    LinearSrgb = 3,
+}
+
+impl LumaModel {
+    const ALL: [LumaModel; 4] = [
+        LumaModel::Average,
+        LumaModel::Bt601,
+        LumaModel::Bt709,
+        LumaModel::LinearSrgb,
+    ];
+
+    fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|model| *model as i32 == id)
+    }
+
+    fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|model| model.name().as_bytes() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LumaModel::Average => "average",
+            LumaModel::Bt601 => "bt601",
+            LumaModel::Bt709 => "bt709",
+            LumaModel::LinearSrgb => "linear-srgb",
+        }
+    }
+
+    fn luma(self, rgb: [f32; 3]) -> f32 {
+        match self {
+            LumaModel::Average => (rgb[0] + rgb[1] + rgb[2]) / 3.0,
+            LumaModel::Bt601 => 0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2],
+            LumaModel::Bt709 | LumaModel::LinearSrgb => {
+                0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+            }
+        }
+    }
+
+    /// Channel values as the model sees them: sRGB-decoded for `LinearSrgb`, else as stored.
+    fn channel_table(self) -> [f32; 256] {
+        core::array::from_fn(|v| {
+            if self != LumaModel::LinearSrgb {
+                return v as f32;
+            }
+
+            let c = v as f32 / 255.0;
+            let linear = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+            linear * 255.0
+        })
+    }
+}
+
+/// Preprocessing applied when turning RGBA into the grayscale image that gets hashed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct GrayOptions {
+    alpha: AlphaMode,
+    /// Background `[r, g, b]` for compositing.
+    background: [u8; 3],
+    luma: LumaModel,
+}
+
+impl Default for GrayOptions {
+    fn default() -> Self {
+        GrayOptions {
+            alpha: AlphaMode::Ignore,
+            background: [255; 3],
+            luma: LumaModel::Average,
+        }
+    }
+}
+
+impl GrayOptions {
+    /// The background only matters when compositing, so it is normalized away otherwise and
+    /// equal options always mean equal output.
+    fn new(alpha: AlphaMode, background_rgb: u32, luma: LumaModel) -> Self {
+        let background = if alpha == AlphaMode::Ignore {
+            GrayOptions::default().background
+        } else {
+            [16, 8, 0].map(|shift| (background_rgb >> shift) as u8)
+        };
+        GrayOptions {
+            alpha,
+            background,
+            luma,
+        }
+    }
+}
+
+/// Checks the buffer against the dimensions and returns the RGBA bytes of the image.
+fn rgba_pixels(image_data: &[u8], width: usize, height: usize) -> Result<&[u8], &'static str> {
     if width == 0 || height == 0 {
         return Err("Invalid dimensions");
     }
@@ -154,29 +304,142 @@ fn calculate_hash_for_rgba(
         return Err("Image data is too short");
     }
 
-    let mut gray: Vec<u16> = Vec::with_capacity(total_pixels);
-    let mut sum: u64 = 0;
+    Ok(&image_data[..expected_bytes])
+}
+
+fn rgba_to_gray(image_data: &[u8], width: usize, height: usize) -> Result<GrayImage, &'static str> {
+    rgba_to_gray_with(image_data, width, height, &GrayOptions::default())
+}
+
+fn rgba_to_gray_with(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    options: &GrayOptions,
+) -> Result<GrayImage, &'static str> {
+    let rgba = rgba_pixels(image_data, width, height)?;
+    let luma = options.luma;
+    // Compositing happens in the model's space, so linear-light blends are physically right.
+    let table = luma.channel_table();
+    let bg = options.background.map(|c| table[c as usize]);
+
+    let pixels = rgba
+        .chunks_exact(4)
+        .map(|px| {
+            let rgb = [px[0], px[1], px[2]].map(|c| table[c as usize]);
+            if options.alpha == AlphaMode::Ignore {
+                return luma.luma(rgb);
+            }
+
+            let alpha = px[3] as f32 / 255.0;
+            luma.luma([0, 1, 2].map(|c| rgb[c] * alpha + bg[c] * (1.0 - alpha)))
+        })
+        .collect();
+
+    Ok(GrayImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// The alpha channel as its own grayscale image.
+fn rgba_alpha_plane(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<GrayImage, &'static str> {
+    let rgba = rgba_pixels(image_data, width, height)?;
+    Ok(GrayImage {
+        width,
+        height,
+        pixels: rgba.chunks_exact(4).map(|px| px[3] as f32).collect(),
+    })
+}
+
+/// Source samples contributing to one output sample along an axis. `weights[k]` is the
+/// normalized weight of source sample `start + k` (its coverage when area-averaging).
+struct ResampleSpan {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn area_spans(src_len: usize, dst_len: usize) -> Vec<ResampleSpan> {
+    let scale = src_len as f64 / dst_len as f64;
+    let mut spans = Vec::with_capacity(dst_len);
+
+    for i in 0..dst_len {
+        let lo = (i as f64 * src_len as f64) / dst_len as f64;
+        let hi = ((i + 1) as f64 * src_len as f64) / dst_len as f64;
+        let start = (lo.floor() as usize).min(src_len - 1);
+        let end = (hi.ceil() as usize).clamp(start + 1, src_len);
+
+        let weights = (start..end)
+            .map(|s| {
+                let overlap = hi.min((s + 1) as f64) - lo.max(s as f64);
+                (overlap.max(0.0) / scale) as f32
+            })
+            .collect();
+
+        spans.push(ResampleSpan { start, weights });
+    }
+
+    spans
+}
+
+/// Box/area-averaging resize: every output sample is the coverage-weighted mean of the
+/// source samples under it, so the result does not depend on the source resolution.
+fn resize_area(src: &GrayImage, dst_width: usize, dst_height: usize) -> GrayImage {
+    if src.width == dst_width && src.height == dst_height {
+        return GrayImage {
+            width: dst_width,
+            height: dst_height,
+            pixels: src.pixels.clone(),
+        };
+    }
+
+    let x_spans = area_spans(src.width, dst_width);
+    let y_spans = area_spans(src.height, dst_height);
+
+    // Horizontal pass: src.width x src.height -> dst_width x src.height.
+    let mut horizontal = Vec::with_capacity(dst_width * src.height);
+    for row in src.pixels.chunks_exact(src.width) {
+        for span in &x_spans {
+            let mut acc = 0f32;
+            for (k, &w) in span.weights.iter().enumerate() {
+                acc += row[span.start + k] * w;
+            }
+            horizontal.push(acc);
+        }
+    }
+
+    // Vertical pass: dst_width x src.height -> dst_width x dst_height.
+    let mut pixels = Vec::with_capacity(dst_width * dst_height);
+    for span in &y_spans {
+        for x in 0..dst_width {
+            let mut acc = 0f32;
+            for (k, &w) in span.weights.iter().enumerate() {
+                acc += horizontal[(span.start + k) * dst_width + x] * w;
+            }
+            pixels.push(acc);
+        }
+    }
 
-    for i in 0..total_pixels {
-        let pixel_index = i * 4;
-        let r = image_data[pixel_index] as u16;
-        let g = image_data[pixel_index + 1] as u16;
-        let b = image_data[pixel_index + 2] as u16;
-        let value = (r + g + b) / 3;
-        gray.push(value);
-        sum += value as u64;
+    GrayImage {
+        width: dst_width,
+        height: dst_height,
+        pixels,
     }
+}
 
-    let mut hex = Vec::with_capacity((total_pixels + 3) / 4);
+fn bits_to_hex(bits: &[bool]) -> String {
+    let mut hex = Vec::with_capacity(bits.len().div_ceil(4));
     let mut nibble: u8 = 0;
     let mut nibble_bits = 0usize;
 
-    let total_pixels_u128 = total_pixels as u128;
-    let sum_u128 = sum as u128;
-
-    for &value in &gray {
+    for &bit in bits {
         nibble <<= 1;
-        if (value as u128) * total_pixels_u128 > sum_u128 {
+        if bit {
             nibble |= 1;
         }
 
@@ -196,844 +459,5904 @@ fn calculate_hash_for_rgba(
     }
 
     // SAFETY: only ASCII bytes in hex.
-    Ok(unsafe { String::from_utf8_unchecked(hex) })
+    unsafe { String::from_utf8_unchecked(hex) }
 }
 
-fn parse_c_hex(ptr: *const u8) -> Option<Vec<u8>> {
-    if ptr.is_null() {
-        return None;
+/// Layout version of tagged hash strings: `v<version>:<algorithm>:<bits>:<hex payload>`.
+/// Bare hex strings (no `v` prefix) are the untagged pre-versioning format.
+const HASH_FORMAT_VERSION: u32 = 1;
+
+// Negative results of hash comparisons. Every value is < 0 so callers that only
+// check `distance >= 0` keep treating them as "not comparable".
+const HASH_ERROR_INVALID: i32 = -1;
+const HASH_ERROR_ALGORITHM_MISMATCH: i32 = -2;
+const HASH_ERROR_LENGTH_MISMATCH: i32 = -3;
+const HASH_ERROR_UNSUPPORTED_VERSION: i32 = -4;
+const HASH_ERROR_PREPROCESSING_MISMATCH: i32 = -5;
+
+/// Algorithm ids written into tagged hashes and reported by `get_hash_info`.
+/// Id 0 is reserved for untagged legacy hashes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum HashAlgorithm {
+    Average = 1,
+    Dct = 2,
+    Difference = 3,
+    DifferenceHv = 4,
+    Wavelet = 5,
+}
+
+impl HashAlgorithm {
+    const ALL: [HashAlgorithm; 5] = [
+        HashAlgorithm::Average,
+        HashAlgorithm::Dct,
+        HashAlgorithm::Difference,
+        HashAlgorithm::DifferenceHv,
+        HashAlgorithm::Wavelet,
+    ];
+
+    fn from_id(id: i32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| *algorithm as i32 == id)
     }
 
-    // SAFETY: caller guarantees ptr is a valid null-terminated C string.
-    let bytes = unsafe { CStr::from_ptr(ptr as *const c_char).to_bytes() };
-    Some(bytes.to_vec())
-}
+    fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().as_bytes() == name)
+    }
 
-struct PackedHash {
-    blocks: Vec<u64>,
-    nibbles: usize,
-    valid: bool,
+    fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Average => "ahash",
+            HashAlgorithm::Dct => "phash",
+            HashAlgorithm::Difference => "dhash",
+            HashAlgorithm::DifferenceHv => "dhash-hv",
+            HashAlgorithm::Wavelet => "whash",
+        }
+    }
 }
 
-fn pack_hex_to_u64_blocks(bytes: &[u8]) -> PackedHash {
-    if bytes.is_empty() {
-        return PackedHash {
-            blocks: Vec::new(),
-            nibbles: 0,
-            valid: false,
+/// Algorithm field of a tagged hash: the algorithm name followed by any preprocessing that
+/// differs from the default, e.g. `ahash+luma=bt709+alpha=composite-ffffff+trim=0-8`.
+/// Hashes made with different preprocessing are not comparable, so it has to be part of
+/// the tag; that includes trimming, which changes the hash of any padded image. Default
+/// preprocessing adds nothing, so the per-algorithm exports keep their plain tags.
+fn algorithm_field(algorithm: HashAlgorithm, options: &PreprocessOptions) -> String {
+    let mut field = algorithm.name().to_string();
+    let gray = &options.gray;
+    if gray.luma != LumaModel::Average {
+        field.push_str("+luma=");
+        field.push_str(gray.luma.name());
+    }
+    if gray.alpha != AlphaMode::Ignore {
+        let [r, g, b] = gray.background;
+        field.push_str(&format!(
+            "+alpha={}-{r:02x}{g:02x}{b:02x}",
+            gray.alpha.name()
+        ));
+    }
+    if options.trim.enabled() {
+        let check = |value: i32| match value {
+            ..0 => "off".to_string(),
+            _ => value.to_string(),
         };
+        field.push_str(&format!(
+            "+trim={}-{}",
+            check(options.trim.alpha_threshold),
+            check(options.trim.tolerance)
+        ));
     }
+    field
+}
 
-    let mut blocks = Vec::with_capacity((bytes.len() + 15) / 16);
-    let mut offset = 0usize;
-
-    while offset < bytes.len() {
-        let end = (offset + 16).min(bytes.len());
-        let mut block = 0u64;
-
-        for &ch in &bytes[offset..end] {
-            block = (block << 4) | hex_to_val(ch) as u64;
+/// Inverse of `algorithm_field`. Only the canonical spelling is accepted, so equal
+/// preprocessing always means an equal tag string.
+fn parse_algorithm_field(field: &[u8]) -> Option<(HashAlgorithm, PreprocessOptions)> {
+    let mut parts = field.split(|&b| b == b'+');
+    let algorithm = HashAlgorithm::from_name(parts.next()?)?;
+    let mut options = PreprocessOptions::default();
+
+    for modifier in parts {
+        let (key, value) = modifier.split_at(modifier.iter().position(|&b| b == b'=')?);
+        let value = &value[1..];
+        match key {
+            b"luma" => options.gray.luma = LumaModel::from_name(value)?,
+            b"alpha" => {
+                let dash = value.iter().rposition(|&b| b == b'-')?;
+                let alpha = AlphaMode::from_name(&value[..dash])?;
+                let background = core::str::from_utf8(&value[dash + 1..]).ok()?;
+                let background = u32::from_str_radix(background, 16).ok()?;
+                options.gray = GrayOptions::new(alpha, background, options.gray.luma);
+            }
+            b"trim" => {
+                let check = |value: &[u8]| match value {
+                    b"off" => Some(-1),
+                    _ => parse_decimal(value).filter(|&v| v <= 255).map(|v| v as i32),
+                };
+                let dash = value.iter().position(|&b| b == b'-')?;
+                options.trim = TrimOptions {
+                    alpha_threshold: check(&value[..dash])?,
+                    tolerance: check(&value[dash + 1..])?,
+                };
+            }
+            _ => return None,
         }
+    }
 
-        let remaining = 16usize - (end - offset);
-        if remaining > 0 {
-            block <<= (remaining * 4) as u32;
-        }
+    (algorithm_field(algorithm, &options).as_bytes() == field).then_some((algorithm, options))
+}
 
-        blocks.push(block);
-        offset = end;
-    }
+fn encode_hash(algorithm: HashAlgorithm, bits: &[bool]) -> String {
+    encode_hash_with(algorithm, &PreprocessOptions::default(), bits)
+}
 
-    PackedHash {
-        blocks,
-        nibbles: bytes.len(),
-        valid: true,
-    }
+fn encode_hash_with(
+    algorithm: HashAlgorithm,
+    options: &PreprocessOptions,
+    bits: &[bool],
+) -> String {
+    format!(
+        "v{}:{}:{}:{}",
+        HASH_FORMAT_VERSION,
+        algorithm_field(algorithm, options),
+        bits.len(),
+        bits_to_hex(bits)
+    )
 }
 
-fn parse_packed_hash(ptr: *const u8) -> PackedHash {
-    match parse_c_hex(ptr) {
-        Some(bytes) => pack_hex_to_u64_blocks(&bytes),
-        None => PackedHash {
-            blocks: Vec::new(),
-            nibbles: 0,
-            valid: false,
-        },
-    }
+fn average_hash_bits(gray: &GrayImage, hash_size: usize) -> Vec<bool> {
+    let small = resize_area(gray, hash_size, hash_size);
+    let sum: f64 = small.pixels.iter().map(|&v| v as f64).sum();
+    let mean = sum / small.pixels.len() as f64;
+
+    small.pixels.iter().map(|&v| v as f64 > mean).collect()
 }
 
-fn hamming_distance_packed(hash1: &PackedHash, hash2: &PackedHash, early_stop: i32) -> i32 {
-    if !hash1.valid || !hash2.valid || hash1.nibbles != hash2.nibbles {
-        return -1;
+/// pHash runs the DCT on an image this many times larger than the kept low-frequency block.
+const DCT_OVERSAMPLE: usize = 4;
+
+/// Rows of the (unnormalized) DCT-II basis: `table[k * n + i] = cos(pi * (2i + 1) * k / 2n)`.
+/// Only the first `keep` frequencies are generated.
+fn dct_table(n: usize, keep: usize) -> Vec<f64> {
+    let mut table = Vec::with_capacity(n * keep);
+    for k in 0..keep {
+        for i in 0..n {
+            let angle = core::f64::consts::PI * ((2 * i + 1) * k) as f64 / (2 * n) as f64;
+            table.push(angle.cos());
+        }
     }
+    table
+}
 
-    let mut distance = 0i32;
-    let mut idx = 0usize;
-    let len = hash1.blocks.len();
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable_by(|a, b| a.total_cmp(b));
 
-    // Unrolled block-wise accumulation to improve wasm backend vectorization opportunities.
-    while idx + 4 <= len {
-        distance += (hash1.blocks[idx] ^ hash2.blocks[idx]).count_ones() as i32;
-        distance += (hash1.blocks[idx + 1] ^ hash2.blocks[idx + 1]).count_ones() as i32;
-        distance += (hash1.blocks[idx + 2] ^ hash2.blocks[idx + 2]).count_ones() as i32;
-        distance += (hash1.blocks[idx + 3] ^ hash2.blocks[idx + 3]).count_ones() as i32;
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
 
-        if early_stop >= 0 && distance > early_stop {
-            return distance;
+fn dct_hash_bits(gray: &GrayImage, hash_size: usize) -> Vec<bool> {
+    let n = hash_size * DCT_OVERSAMPLE;
+    let small = resize_area(gray, n, n);
+    let table = dct_table(n, hash_size);
+
+    // Row transform, keeping only the low horizontal frequencies: n rows x hash_size.
+    let mut rows = vec![0f64; n * hash_size];
+    for y in 0..n {
+        let row = &small.pixels[y * n..(y + 1) * n];
+        for u in 0..hash_size {
+            let basis = &table[u * n..(u + 1) * n];
+            let mut acc = 0f64;
+            for x in 0..n {
+                acc += row[x] as f64 * basis[x];
+            }
+            rows[y * hash_size + u] = acc;
         }
-
-        idx += 4;
     }
 
-    while idx < len {
-        distance += (hash1.blocks[idx] ^ hash2.blocks[idx]).count_ones() as i32;
-        if early_stop >= 0 && distance > early_stop {
-            return distance;
+    // Column transform over the kept block: hash_size x hash_size coefficients.
+    let mut coefficients = vec![0f64; hash_size * hash_size];
+    for v in 0..hash_size {
+        let basis = &table[v * n..(v + 1) * n];
+        for u in 0..hash_size {
+            let mut acc = 0f64;
+            for y in 0..n {
+                acc += rows[y * hash_size + u] * basis[y];
+            }
+            coefficients[v * hash_size + u] = acc;
         }
-        idx += 1;
     }
 
-    distance
+    let threshold = median(&coefficients);
+    coefficients.iter().map(|&c| c > threshold).collect()
 }
 
-fn alloc_i32_array(values: &[i32]) -> *mut i32 {
-    if values.is_empty() {
-        return null_mut();
-    }
-
-    let Some(total_bytes) = values.len().checked_mul(size_of::<i32>()) else {
-        return null_mut();
+/// dHash: one bit per horizontally adjacent pair (left < right) over a
+/// `(hash_size + 1) x hash_size` downscale, optionally followed by the vertical
+/// (top < bottom) bits over a `hash_size x (hash_size + 1)` downscale.
+fn difference_hash_bits(gray: &GrayImage, hash_size: usize, include_columns: bool) -> Vec<bool> {
+    let bit_count = if include_columns {
+        hash_size * hash_size * 2
+    } else {
+        hash_size * hash_size
     };
+    let mut bits = Vec::with_capacity(bit_count);
 
-    let ptr = alloc_bytes(total_bytes) as *mut i32;
-    if ptr.is_null() {
-        return null_mut();
+    let wide = resize_area(gray, hash_size + 1, hash_size);
+    for row in wide.pixels.chunks_exact(hash_size + 1) {
+        bits.extend(row.windows(2).map(|pair| pair[1] > pair[0]));
     }
 
-    // SAFETY: destination has enough space for values.len() i32s.
-    unsafe {
-        ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+    if include_columns {
+        let tall = resize_area(gray, hash_size, hash_size + 1);
+        for y in 0..hash_size {
+            for x in 0..hash_size {
+                bits.push(tall.pixels[(y + 1) * hash_size + x] > tall.pixels[y * hash_size + x]);
+            }
+        }
     }
 
-    ptr
+    bits
 }
 
-#[no_mangle]
-pub extern "C" fn calculate_perceptual_hash(
-    image_data: *const u8,
-    width: i32,
-    height: i32,
-    hash_size: i32,
-) -> *mut HashResult {
-    if image_data.is_null() || width <= 0 || height <= 0 || hash_size <= 0 {
-        return create_error_result("Invalid input parameters");
+/// Largest working image side for wHash (`hash_size << level`).
+const MAX_WAVELET_SCALE: usize = 1024;
+
+/// Picks the deepest Haar level whose working image still fits inside the source,
+/// so wHash never has to upsample unless the source is smaller than the hash itself.
+fn default_wavelet_level(width: usize, height: usize, hash_size: usize) -> usize {
+    let limit = width.min(height).min(MAX_WAVELET_SCALE);
+    let mut level = 0;
+    while (hash_size << (level + 1)) <= limit {
+        level += 1;
     }
+    level
+}
 
-    let width_usize = width as usize;
-    let height_usize = height as usize;
+/// One level of the 2D Haar transform on the top-left `size x size` block of a
+/// row-major matrix with row length `stride`. Averages go to the top-left quadrant,
+/// details to the other three.
+fn haar_forward(data: &mut [f64], size: usize, stride: usize) {
+    let half = size / 2;
+    let mut line = vec![0f64; size];
+
+    for y in 0..size {
+        let row = &mut data[y * stride..y * stride + size];
+        for i in 0..half {
+            line[i] = (row[2 * i] + row[2 * i + 1]) / 2.0;
+            line[half + i] = (row[2 * i] - row[2 * i + 1]) / 2.0;
+        }
+        row.copy_from_slice(&line);
+    }
 
-    let Some(image_len) = width_usize
-        .checked_mul(height_usize)
-        .and_then(|v| v.checked_mul(4))
-    else {
-        return create_error_result("Image size overflow");
-    };
+    for x in 0..size {
+        for i in 0..half {
+            let a = data[2 * i * stride + x];
+            let b = data[(2 * i + 1) * stride + x];
+            line[i] = (a + b) / 2.0;
+            line[half + i] = (a - b) / 2.0;
+        }
+        for (y, &value) in line.iter().enumerate() {
+            data[y * stride + x] = value;
+        }
+    }
+}
 
-    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
-    let image_slice = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+/// Inverse of [`haar_forward`] for the same block.
+fn haar_inverse(data: &mut [f64], size: usize, stride: usize) {
+    let half = size / 2;
+    let mut line = vec![0f64; size];
+
+    for x in 0..size {
+        for i in 0..half {
+            let low = data[i * stride + x];
+            let high = data[(half + i) * stride + x];
+            line[2 * i] = low + high;
+            line[2 * i + 1] = low - high;
+        }
+        for (y, &value) in line.iter().enumerate() {
+            data[y * stride + x] = value;
+        }
+    }
 
-    match calculate_hash_for_rgba(image_slice, width_usize, height_usize) {
-        Ok(hash) => {
-            let hash_ptr = alloc_c_string(&hash);
-            if hash_ptr.is_null() {
-                return create_error_result("Failed to allocate hash result");
-            }
-            create_hash_result(hash_ptr, 0, null_mut())
+    for y in 0..size {
+        let row = &mut data[y * stride..y * stride + size];
+        for i in 0..half {
+            line[2 * i] = row[i] + row[half + i];
+            line[2 * i + 1] = row[i] - row[half + i];
         }
-        Err(error) => create_error_result(error),
+        row.copy_from_slice(&line);
     }
 }
 
-#[no_mangle]
-pub extern "C" fn calculate_batch_hashes(
-    images_data: *const u8,
-    dimensions: *const i32,
-    image_offsets: *const i32,
-    num_images: i32,
-    hash_size: i32,
-) -> *mut HashResult {
-    if images_data.is_null()
-        || dimensions.is_null()
-        || image_offsets.is_null()
-        || num_images <= 0
-        || hash_size <= 0
-    {
-        return null_mut();
+/// wHash: area-downscale to `hash_size << level`, apply `level` Haar steps and threshold
+/// the resulting `hash_size x hash_size` approximation band on its median. With
+/// `remove_low_band`, the image is first fully decomposed, its coarsest approximation
+/// band is zeroed and it is reconstructed, as in the reference wHash.
+fn wavelet_hash_bits(
+    gray: &GrayImage,
+    hash_size: usize,
+    level: usize,
+    remove_low_band: bool,
+) -> Vec<bool> {
+    let scale = hash_size << level;
+    let small = resize_area(gray, scale, scale);
+    let mut data: Vec<f64> = small.pixels.iter().map(|&v| v as f64 / 255.0).collect();
+
+    if remove_low_band {
+        let mut sizes = Vec::new();
+        let mut size = scale;
+        while size > 1 && size.is_multiple_of(2) {
+            haar_forward(&mut data, size, scale);
+            sizes.push(size);
+            size /= 2;
+        }
+
+        for y in 0..size {
+            data[y * scale..y * scale + size].fill(0.0);
+        }
+
+        for &size in sizes.iter().rev() {
+            haar_inverse(&mut data, size, scale);
+        }
     }
 
-    let num = num_images as usize;
-    let total_bytes = match num.checked_mul(size_of::<HashResult>()) {
-        Some(size) => size,
-        None => return null_mut(),
-    };
+    let mut size = scale;
+    for _ in 0..level {
+        haar_forward(&mut data, size, scale);
+        size /= 2;
+    }
 
-    let results_ptr = alloc_bytes(total_bytes) as *mut HashResult;
-    if results_ptr.is_null() {
-        return null_mut();
+    let mut low_band = Vec::with_capacity(hash_size * hash_size);
+    for y in 0..hash_size {
+        low_band.extend_from_slice(&data[y * scale..y * scale + hash_size]);
     }
 
-    // SAFETY: pointers are valid and lengths are controlled by caller.
-    let dims = unsafe { core::slice::from_raw_parts(dimensions, num * 2) };
-    let offsets = unsafe { core::slice::from_raw_parts(image_offsets, num) };
+    let threshold = median(&low_band);
+    low_band.iter().map(|&c| c > threshold).collect()
+}
 
-    for i in 0..num {
-        let width = dims[i * 2];
-        let height = dims[i * 2 + 1];
-        let offset = offsets[i];
-
-        let entry = if width <= 0 || height <= 0 || offset < 0 {
-            HashResult {
-                hash: null_mut(),
-                error: 1,
-                error_message: alloc_c_string("Invalid image metadata"),
-            }
-        } else {
-            let width_usize = width as usize;
-            let height_usize = height as usize;
-            match width_usize
-                .checked_mul(height_usize)
-                .and_then(|v| v.checked_mul(4))
-            {
-                Some(image_len) => {
-                    // SAFETY: caller guarantees images_data has enough bytes from offset.
-                    let image_ptr = unsafe { images_data.add(offset as usize) };
-                    // SAFETY: image_ptr points to at least image_len bytes.
-                    let image_slice = unsafe { core::slice::from_raw_parts(image_ptr, image_len) };
-
-                    match calculate_hash_for_rgba(image_slice, width_usize, height_usize) {
-                        Ok(hash) => {
-                            let hash_ptr = alloc_c_string(&hash);
-                            if hash_ptr.is_null() {
-                                HashResult {
-                                    hash: null_mut(),
-                                    error: 1,
-                                    error_message: alloc_c_string("Failed to allocate hash"),
-                                }
-                            } else {
-                                HashResult {
-                                    hash: hash_ptr,
-                                    error: 0,
-                                    error_message: null_mut(),
-                                }
-                            }
-                        }
-                        Err(error) => HashResult {
-                            hash: null_mut(),
-                            error: 1,
-                            error_message: alloc_c_string(error),
-                        },
-                    }
-                }
-                None => HashResult {
-                    hash: null_mut(),
-                    error: 1,
-                    error_message: alloc_c_string("Image size overflow"),
-                },
-            }
-        };
-
-        // SAFETY: results_ptr points to an array of num HashResult entries.
-        unsafe {
-            results_ptr.add(i).write(entry);
-        }
+fn check_hash_size(hash_size: usize) -> Result<(), &'static str> {
+    if hash_size == 0 || hash_size > MAX_HASH_SIZE {
+        return Err("Unsupported hash size");
     }
+    Ok(())
+}
 
-    results_ptr
+fn calculate_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    hash_size: usize,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let gray = rgba_to_gray(image_data, width, height)?;
+    Ok(encode_hash(
+        HashAlgorithm::Average,
+        &average_hash_bits(&gray, hash_size),
+    ))
 }
 
-#[no_mangle]
-pub extern "C" fn calculate_hamming_distance(hash1: *const u8, hash2: *const u8) -> i32 {
-    let packed1 = parse_packed_hash(hash1);
-    let packed2 = parse_packed_hash(hash2);
-    hamming_distance_packed(&packed1, &packed2, -1)
+fn calculate_dct_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    hash_size: usize,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let gray = rgba_to_gray(image_data, width, height)?;
+    Ok(encode_hash(
+        HashAlgorithm::Dct,
+        &dct_hash_bits(&gray, hash_size),
+    ))
 }
 
-#[no_mangle]
-pub extern "C" fn find_similar_pairs(
-    hashes: *const *const u8,
-    num_hashes: i32,
-    threshold: i32,
-    out_count: *mut i32,
-) -> *mut i32 {
-    if !out_count.is_null() {
-        // SAFETY: out_count points to writable memory.
-        unsafe {
-            *out_count = 0;
-        }
-    }
+fn calculate_difference_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    hash_size: usize,
+    include_columns: bool,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let gray = rgba_to_gray(image_data, width, height)?;
+    let algorithm = if include_columns {
+        HashAlgorithm::DifferenceHv
+    } else {
+        HashAlgorithm::Difference
+    };
+    Ok(encode_hash(
+        algorithm,
+        &difference_hash_bits(&gray, hash_size, include_columns),
+    ))
+}
 
-    if hashes.is_null() || num_hashes <= 1 {
-        return null_mut();
+fn calculate_wavelet_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    hash_size: usize,
+    level: Option<usize>,
+    remove_low_band: bool,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let level = level.unwrap_or_else(|| default_wavelet_level(width, height, hash_size));
+    if level > MAX_WAVELET_SCALE.ilog2() as usize || (hash_size << level) > MAX_WAVELET_SCALE {
+        return Err("Unsupported wavelet level");
     }
 
-    let num = num_hashes as usize;
-    // SAFETY: hashes points to num pointers.
-    let hash_ptrs = unsafe { core::slice::from_raw_parts(hashes, num) };
+    let gray = rgba_to_gray(image_data, width, height)?;
+    Ok(encode_hash(
+        HashAlgorithm::Wavelet,
+        &wavelet_hash_bits(&gray, hash_size, level, remove_low_band),
+    ))
+}
 
-    let mut parsed_hashes: Vec<PackedHash> = Vec::with_capacity(num);
-    for &hash_ptr in hash_ptrs {
-        parsed_hashes.push(parse_packed_hash(hash_ptr));
+/// Hash bits of `gray` for `algorithm` using its default parameters (no wavelet low-band
+/// removal, automatic wavelet level), matching the single-algorithm exports.
+fn gray_hash_bits(gray: &GrayImage, algorithm: HashAlgorithm, hash_size: usize) -> Vec<bool> {
+    match algorithm {
+        HashAlgorithm::Average => average_hash_bits(gray, hash_size),
+        HashAlgorithm::Dct => dct_hash_bits(gray, hash_size),
+        HashAlgorithm::Difference => difference_hash_bits(gray, hash_size, false),
+        HashAlgorithm::DifferenceHv => difference_hash_bits(gray, hash_size, true),
+        HashAlgorithm::Wavelet => {
+            let level = default_wavelet_level(gray.width, gray.height, hash_size);
+            wavelet_hash_bits(gray, hash_size, level, false)
+        }
     }
+}
 
-    let mut pairs: Vec<i32> = Vec::new();
-    let threshold_for_early_stop = threshold.max(0);
+/// The grayscale image to hash, plus the alpha plane when `AlphaMode::Channel` hashes it too.
+struct HashPlanes {
+    gray: GrayImage,
+    alpha: Option<GrayImage>,
+}
 
-    for i in 0..num {
-        for j in (i + 1)..num {
-            let distance = hamming_distance_packed(
-                &parsed_hashes[i],
-                &parsed_hashes[j],
-                threshold_for_early_stop,
-            );
-            if distance >= 0 && distance <= threshold {
-                pairs.push(i as i32);
-                pairs.push(j as i32);
-            }
-        }
+fn hash_planes(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    options: &GrayOptions,
+) -> Result<HashPlanes, &'static str> {
+    let gray = rgba_to_gray_with(image_data, width, height, options)?;
+    let alpha = match options.alpha {
+        AlphaMode::Channel => Some(rgba_alpha_plane(image_data, width, height)?),
+        AlphaMode::Ignore | AlphaMode::Composite => None,
+    };
+    Ok(HashPlanes { gray, alpha })
+}
+
+/// Hash bits of the planes: the grayscale hash, followed by the alpha-plane hash if any.
+fn planes_hash_bits(planes: &HashPlanes, algorithm: HashAlgorithm, hash_size: usize) -> Vec<bool> {
+    let mut bits = gray_hash_bits(&planes.gray, algorithm, hash_size);
+    if let Some(alpha) = &planes.alpha {
+        bits.extend(gray_hash_bits(alpha, algorithm, hash_size));
     }
+    bits
+}
 
-    let pair_count = (pairs.len() / 2) as i32;
-    if !out_count.is_null() {
-        // SAFETY: out_count points to writable memory.
-        unsafe {
-            *out_count = pair_count;
-        }
+/// Number of bits `algorithm` produces at `hash_size` under `alpha`.
+fn hash_bit_count(algorithm: HashAlgorithm, hash_size: usize, alpha: AlphaMode) -> usize {
+    let bits = match algorithm {
+        HashAlgorithm::DifferenceHv => 2 * hash_size * hash_size,
+        _ => hash_size * hash_size,
+    };
+    match alpha {
+        AlphaMode::Channel => 2 * bits,
+        AlphaMode::Ignore | AlphaMode::Composite => bits,
     }
+}
 
-    alloc_i32_array(&pairs)
+/// Hash bits with explicit grayscale preprocessing. `AlphaMode::Channel` appends the
+/// alpha-plane hash, doubling the bit length.
+fn hash_bits_with_options(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    algorithm: HashAlgorithm,
+    hash_size: usize,
+    options: &GrayOptions,
+) -> Result<Vec<bool>, &'static str> {
+    check_hash_size(hash_size)?;
+    let planes = hash_planes(image_data, width, height, options)?;
+    Ok(planes_hash_bits(&planes, algorithm, hash_size))
 }
 
-#[no_mangle]
-pub extern "C" fn find_similar_pairs_bucketed(
-    hashes: *const *const u8,
-    num_hashes: i32,
-    bucket_starts: *const i32,
-    bucket_sizes: *const i32,
-    num_buckets: i32,
-    threshold: i32,
-    out_count: *mut i32,
-) -> *mut i32 {
-    if !out_count.is_null() {
-        // SAFETY: out_count points to writable memory.
-        unsafe {
-            *out_count = 0;
+/// Preprocessing taken by the option-taking hash exports: border trimming followed by the
+/// grayscale conversion.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PreprocessOptions {
+    gray: GrayOptions,
+    trim: TrimOptions,
+}
+
+impl Default for PreprocessOptions {
+    fn default() -> Self {
+        PreprocessOptions {
+            gray: GrayOptions::default(),
+            trim: TrimOptions {
+                alpha_threshold: -1,
+                tolerance: -1,
+            },
         }
     }
+}
 
-    if hashes.is_null() || bucket_starts.is_null() || bucket_sizes.is_null() {
-        return null_mut();
+/// Length in i32s of a preprocessing options block.
+const PREPROCESS_OPTION_FIELDS: usize = 5;
+
+impl PreprocessOptions {
+    /// The options block `read_preprocess_options` would read back as these options.
+    fn to_fields(self) -> [i32; PREPROCESS_OPTION_FIELDS] {
+        let [r, g, b] = self.gray.background.map(i32::from);
+        [
+            self.gray.alpha as i32,
+            (r << 16) | (g << 8) | b,
+            self.gray.luma as i32,
+            self.trim.alpha_threshold,
+            self.trim.tolerance,
+        ]
     }
+}
 
-    if num_hashes <= 1 || num_buckets <= 0 {
-        return null_mut();
+/// Reads a preprocessing options block of `PREPROCESS_OPTION_FIELDS` i32s:
+/// `[alpha_mode, background_rgb, luma_model, trim_alpha_threshold, trim_tolerance]`.
+/// A null pointer selects the defaults (alpha ignored, averaged RGB, no trimming).
+fn read_preprocess_options(options: *const i32) -> Result<PreprocessOptions, &'static str> {
+    if options.is_null() {
+        return Ok(PreprocessOptions::default());
     }
 
-    let num_hashes_usize = num_hashes as usize;
-    let num_buckets_usize = num_buckets as usize;
+    // SAFETY: caller guarantees options points to PREPROCESS_OPTION_FIELDS i32s.
+    let fields = unsafe { core::slice::from_raw_parts(options, PREPROCESS_OPTION_FIELDS) };
+    let alpha = AlphaMode::from_id(fields[0]).ok_or("Unknown alpha mode")?;
+    let luma = LumaModel::from_id(fields[2]).ok_or("Unknown luma model")?;
+    // Any negative value disables a check; keep one spelling so equal options tag alike.
+    let trim = TrimOptions {
+        alpha_threshold: fields[3].max(-1),
+        tolerance: fields[4].max(-1),
+    };
+    if !trim_options_valid(&trim) {
+        return Err("Invalid trim options");
+    }
 
-    // SAFETY: caller guarantees valid arrays with these lengths.
-    let hash_ptrs = unsafe { core::slice::from_raw_parts(hashes, num_hashes_usize) };
-    let starts = unsafe { core::slice::from_raw_parts(bucket_starts, num_buckets_usize) };
-    let sizes = unsafe { core::slice::from_raw_parts(bucket_sizes, num_buckets_usize) };
+    Ok(PreprocessOptions {
+        gray: GrayOptions::new(alpha, fields[1] as u32, luma),
+        trim,
+    })
+}
+
+/// Hash bits after full preprocessing: the borders are trimmed, then the rest is hashed
+/// as in `hash_bits_with_options`.
+fn preprocessed_hash_bits(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    algorithm: HashAlgorithm,
+    hash_size: usize,
+    options: &PreprocessOptions,
+) -> Result<Vec<bool>, &'static str> {
+    let image_data = rgba_pixels(image_data, width, height)?;
+    let (data, width, height) = trim_rgba(image_data, width, height, &options.trim);
+    hash_bits_with_options(&data, width, height, algorithm, hash_size, &options.gray)
+}
+
+fn calculate_hash_with_options_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    algorithm: HashAlgorithm,
+    hash_size: usize,
+    options: &PreprocessOptions,
+) -> Result<String, &'static str> {
+    let bits = preprocessed_hash_bits(image_data, width, height, algorithm, hash_size, options)?;
+    Ok(encode_hash_with(algorithm, options, &bits))
+}
 
-    let mut parsed_hashes: Vec<PackedHash> = Vec::with_capacity(num_hashes_usize);
-    for &hash_ptr in hash_ptrs {
-        parsed_hashes.push(parse_packed_hash(hash_ptr));
+fn parse_c_hex(ptr: *const u8) -> Option<Vec<u8>> {
+    if ptr.is_null() {
+        return None;
     }
 
-    let mut pairs: Vec<i32> = Vec::new();
-    let threshold_for_early_stop = threshold.max(0);
+    // SAFETY: caller guarantees ptr is a valid null-terminated C string.
+    let bytes = unsafe { CStr::from_ptr(ptr as *const c_char).to_bytes() };
+    Some(bytes.to_vec())
+}
 
-    // Compare within each bucket.
-    for b in 0..num_buckets_usize {
-        let start = starts[b];
-        let size = sizes[b];
+struct PackedHash {
+    blocks: Vec<u64>,
+    bits: usize,
+    /// Tagged format version, 0 for bare hex.
+    version: u32,
+    /// `HashAlgorithm` id, 0 for bare hex.
+    algorithm: u8,
+    /// Preprocessing recorded in the tag; the default for bare hex.
+    options: PreprocessOptions,
+    valid: bool,
+}
 
-        if start < 0 || size <= 0 {
-            continue;
+impl PackedHash {
+    fn invalid(version: u32) -> Self {
+        PackedHash {
+            blocks: Vec::new(),
+            bits: 0,
+            version,
+            algorithm: 0,
+            options: PreprocessOptions::default(),
+            valid: false,
         }
+    }
+}
 
-        let start_usize = start as usize;
-        let size_usize = size as usize;
+fn pack_hex_to_u64_blocks(bytes: &[u8]) -> PackedHash {
+    if bytes.is_empty() {
+        return PackedHash::invalid(0);
+    }
 
-        if start_usize >= num_hashes_usize {
-            continue;
-        }
+    let mut blocks = Vec::with_capacity(bytes.len().div_ceil(16));
+    let mut offset = 0usize;
 
-        let end = start_usize.saturating_add(size_usize).min(num_hashes_usize);
+    while offset < bytes.len() {
+        let end = (offset + 16).min(bytes.len());
+        let mut block = 0u64;
 
-        for i in start_usize..end {
-            for j in (i + 1)..end {
-                let distance = hamming_distance_packed(
-                    &parsed_hashes[i],
-                    &parsed_hashes[j],
-                    threshold_for_early_stop,
-                );
-                if distance >= 0 && distance <= threshold {
-                    pairs.push(i as i32);
-                    pairs.push(j as i32);
-                }
-            }
+        for &ch in &bytes[offset..end] {
+            block = (block << 4) | hex_to_val(ch) as u64;
         }
+
+        let remaining = 16usize - (end - offset);
+        if remaining > 0 {
+            block <<= (remaining * 4) as u32;
+        }
+
+        blocks.push(block);
+        offset = end;
     }
 
-    // Compare adjacent buckets to catch near-prefix matches.
-    if num_buckets_usize > 1 {
-        for b in 0..(num_buckets_usize - 1) {
-            let start1 = starts[b];
-            let size1 = sizes[b];
-            let start2 = starts[b + 1];
-            let size2 = sizes[b + 1];
-
-            if start1 < 0 || size1 <= 0 || start2 < 0 || size2 <= 0 {
-                continue;
-            }
+    PackedHash {
+        blocks,
+        bits: bytes.len() * 4,
+        version: 0,
+        algorithm: 0,
+        options: PreprocessOptions::default(),
+        valid: true,
+    }
+}
 
-            let range1_start = start1 as usize;
-            let range2_start = start2 as usize;
-            if range1_start >= num_hashes_usize || range2_start >= num_hashes_usize {
-                continue;
-            }
+fn parse_decimal(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() || bytes.len() > 9 || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .fold(0usize, |acc, &b| acc * 10 + (b - b'0') as usize),
+    )
+}
 
-            let range1_end = range1_start
-                .saturating_add(size1 as usize)
-                .min(num_hashes_usize);
-            let range2_end = range2_start
-                .saturating_add(size2 as usize)
-                .min(num_hashes_usize);
-
-            for i in range1_start..range1_end {
-                for j in range2_start..range2_end {
-                    let distance = hamming_distance_packed(
-                        &parsed_hashes[i],
-                        &parsed_hashes[j],
-                        threshold_for_early_stop,
-                    );
-                    if distance >= 0 && distance <= threshold {
-                        pairs.push(i as i32);
-                        pairs.push(j as i32);
-                    }
-                }
-            }
-        }
+/// Parses `v<version>:<algorithm>:<bits>:<hex>`. Unlike bare hex, the payload is
+/// validated strictly: it must be exactly `ceil(bits / 4)` hex digits.
+fn parse_tagged_hash(bytes: &[u8]) -> PackedHash {
+    let mut fields = bytes[1..].splitn(4, |&b| b == b':');
+    let Some(version) = fields.next().and_then(parse_decimal) else {
+        return PackedHash::invalid(0);
+    };
+    if version as u32 != HASH_FORMAT_VERSION {
+        return PackedHash::invalid(version as u32);
     }
 
-    let pair_count = (pairs.len() / 2) as i32;
-    if !out_count.is_null() {
-        // SAFETY: out_count points to writable memory.
-        unsafe {
-            *out_count = pair_count;
-        }
+    let (Some(name), Some(bits), Some(payload)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return PackedHash::invalid(0);
+    };
+    let Some((algorithm, options)) = parse_algorithm_field(name) else {
+        return PackedHash::invalid(0);
+    };
+    let Some(bits) = parse_decimal(bits).filter(|&bits| bits > 0) else {
+        return PackedHash::invalid(0);
+    };
+    if payload.len() != bits.div_ceil(4) || !payload.iter().all(u8::is_ascii_hexdigit) {
+        return PackedHash::invalid(0);
     }
 
-    alloc_i32_array(&pairs)
+    let mut packed = pack_hex_to_u64_blocks(payload);
+    packed.bits = bits;
+    packed.version = version as u32;
+    packed.algorithm = algorithm as u8;
+    packed.options = options;
+    packed
 }
 
-#[no_mangle]
-pub extern "C" fn free_hash_result(result: *mut HashResult) {
-    if result.is_null() {
-        return;
+/// What makes a set of hashes mutually comparable: their tag without the payload, in the
+/// canonical form `v<version>:<algorithm field>:<bits>`.
+#[derive(PartialEq, Eq)]
+struct HashTag {
+    text: String,
+    bits: usize,
+}
+
+/// Reads the tag of a tagged hash, given either in full or as just
+/// `v<version>:<algorithm field>:<bits>`. Bare hex and unknown versions have no tag.
+fn parse_hash_tag(bytes: &[u8]) -> Option<HashTag> {
+    if bytes.first() != Some(&b'v') {
+        return None;
     }
 
-    // SAFETY: result is a valid pointer returned by create_hash_result.
-    unsafe {
-        let value = result.read();
-        if !value.hash.is_null() {
-            dealloc_bytes(value.hash);
+    let (algorithm, options, bits) = if bytes.iter().filter(|&&b| b == b':').count() == 3 {
+        let packed = parse_tagged_hash(bytes);
+        if !packed.valid {
+            return None;
         }
-        if !value.error_message.is_null() {
-            dealloc_bytes(value.error_message);
+        let algorithm = HashAlgorithm::from_id(packed.algorithm as i32)?;
+        (algorithm, packed.options, packed.bits)
+    } else {
+        let mut fields = bytes[1..].split(|&b| b == b':');
+        let (Some(version), Some(name), Some(bits), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return None;
+        };
+        if parse_decimal(version)? as u32 != HASH_FORMAT_VERSION {
+            return None;
         }
+        let (algorithm, options) = parse_algorithm_field(name)?;
+        let bits = parse_decimal(bits).filter(|&bits| bits > 0)?;
+        (algorithm, options, bits)
+    };
+
+    Some(HashTag {
+        text: format!(
+            "v{HASH_FORMAT_VERSION}:{}:{bits}",
+            algorithm_field(algorithm, &options)
+        ),
+        bits,
+    })
+}
+
+fn parse_hash_bytes(bytes: &[u8]) -> PackedHash {
+    if bytes.first() == Some(&b'v') {
+        parse_tagged_hash(bytes)
+    } else {
+        pack_hex_to_u64_blocks(bytes)
     }
+}
 
-    dealloc_bytes(result as *mut u8);
+fn parse_packed_hash(ptr: *const u8) -> PackedHash {
+    match parse_c_hex(ptr) {
+        Some(bytes) => parse_hash_bytes(&bytes),
+        None => PackedHash::invalid(0),
+    }
 }
 
-#[no_mangle]
-pub extern "C" fn free_batch_results(results: *mut HashResult, num_results: i32) {
-    if results.is_null() || num_results <= 0 {
-        return;
+/// Reason two parsed hashes cannot be compared, if any.
+fn hash_compatibility(hash1: &PackedHash, hash2: &PackedHash) -> Result<(), i32> {
+    if hash1.version > HASH_FORMAT_VERSION || hash2.version > HASH_FORMAT_VERSION {
+        return Err(HASH_ERROR_UNSUPPORTED_VERSION);
     }
+    if !hash1.valid || !hash2.valid {
+        return Err(HASH_ERROR_INVALID);
+    }
+    if hash1.algorithm != hash2.algorithm {
+        return Err(HASH_ERROR_ALGORITHM_MISMATCH);
+    }
+    if hash1.options != hash2.options {
+        return Err(HASH_ERROR_PREPROCESSING_MISMATCH);
+    }
+    if hash1.bits != hash2.bits {
+        return Err(HASH_ERROR_LENGTH_MISMATCH);
+    }
+    Ok(())
+}
 
-    let len = num_results as usize;
+fn hamming_distance_words(blocks1: &[u64], blocks2: &[u64], early_stop: i32) -> i32 {
+    let mut distance = 0i32;
+    let mut idx = 0usize;
+    let len = blocks1.len().min(blocks2.len());
 
-    for i in 0..len {
-        // SAFETY: results points to an array of len HashResult entries.
-        let entry = unsafe { results.add(i).read() };
-        if !entry.hash.is_null() {
-            dealloc_bytes(entry.hash);
+    // Unrolled block-wise accumulation to improve wasm backend vectorization opportunities.
+    while idx + 4 <= len {
+        distance += (blocks1[idx] ^ blocks2[idx]).count_ones() as i32;
+        distance += (blocks1[idx + 1] ^ blocks2[idx + 1]).count_ones() as i32;
+        distance += (blocks1[idx + 2] ^ blocks2[idx + 2]).count_ones() as i32;
+        distance += (blocks1[idx + 3] ^ blocks2[idx + 3]).count_ones() as i32;
+
+        if early_stop >= 0 && distance > early_stop {
+            return distance;
         }
-        if !entry.error_message.is_null() {
-            dealloc_bytes(entry.error_message);
+
+        idx += 4;
+    }
+
+    while idx < len {
+        distance += (blocks1[idx] ^ blocks2[idx]).count_ones() as i32;
+        if early_stop >= 0 && distance > early_stop {
+            return distance;
         }
+        idx += 1;
     }
 
-    dealloc_bytes(results as *mut u8);
+    distance
 }
 
-#[no_mangle]
-pub extern "C" fn free_pairs(pairs: *mut i32) {
-    dealloc_bytes(pairs as *mut u8);
+fn hamming_distance_packed(hash1: &PackedHash, hash2: &PackedHash, early_stop: i32) -> i32 {
+    if let Err(code) = hash_compatibility(hash1, hash2) {
+        return code;
+    }
+
+    hamming_distance_words(&hash1.blocks, &hash2.blocks, early_stop)
 }
 
-#[no_mangle]
-pub extern "C" fn has_simd_support() -> i32 {
-    if cfg!(target_feature = "simd128") {
-        1
-    } else {
-        0
+/// Packs hash bits MSB-first into `out`, matching the layout `pack_hex_to_u64_blocks`
+/// produces for the equivalent hex payload.
+fn bits_to_blocks(bits: &[bool], out: &mut [u64]) {
+    out.fill(0);
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit {
+            out[i / 64] |= 1u64 << (63 - (i % 64));
+        }
     }
 }
 
-// ===== Color Quantization =====
-
-/// Result struct for color quantization operations.
-/// `colors_ptr` points to a flat array of `[r, g, b, population]` u32 tuples.
-#[repr(C)]
-pub struct ColorResult {
-    pub colors_ptr: *mut u32,
-    pub num_colors: i32,
-    pub error: i32,
-    pub error_message: *mut u8,
+/// Borrowed view over hashes stored back to back as `words` u64 blocks each.
+#[derive(Clone, Copy)]
+struct HashBlocks<'a> {
+    data: &'a [u64],
+    words: usize,
 }
 
-fn create_color_result(
-    colors_ptr: *mut u32,
-    num_colors: i32,
-    error: i32,
-    error_message: *mut u8,
-) -> *mut ColorResult {
-    let ptr = alloc_bytes(size_of::<ColorResult>()) as *mut ColorResult;
-    if ptr.is_null() {
-        if !colors_ptr.is_null() {
-            dealloc_bytes(colors_ptr as *mut u8);
-        }
-        if !error_message.is_null() {
-            dealloc_bytes(error_message);
+impl<'a> HashBlocks<'a> {
+    fn from_raw(blocks: *const u64, num_hashes: i32, words_per_hash: i32) -> Option<Self> {
+        if blocks.is_null() || num_hashes <= 0 || words_per_hash <= 0 {
+            return None;
         }
-        return null_mut();
-    }
 
-    unsafe {
-        ptr.write(ColorResult {
-            colors_ptr,
-            num_colors,
-            error,
-            error_message,
-        });
+        let words = words_per_hash as usize;
+        let total = (num_hashes as usize).checked_mul(words)?;
+
+        // SAFETY: caller guarantees blocks holds num_hashes * words_per_hash u64s.
+        let data = unsafe { core::slice::from_raw_parts(blocks, total) };
+        Some(HashBlocks { data, words })
     }
 
-    ptr
-}
+    fn len(&self) -> usize {
+        self.data.len() / self.words
+    }
 
-fn create_color_error(message: &str) -> *mut ColorResult {
-    let error_message = alloc_c_string(message);
-    create_color_result(null_mut(), 0, 1, error_message)
+    fn get(&self, index: usize) -> &'a [u64] {
+        &self.data[index * self.words..(index + 1) * self.words]
+    }
 }
 
-/// Allocate and populate a flat u32 array with [r, g, b, population] tuples.
-fn alloc_color_array(colors: &[(u32, u32, u32, u32)]) -> *mut u32 {
-    if colors.is_empty() {
+fn alloc_array<T: Copy>(values: &[T]) -> *mut T {
+    if values.is_empty() {
         return null_mut();
     }
 
-    let total_u32s = colors.len() * 4;
-    let Some(total_bytes) = total_u32s.checked_mul(size_of::<u32>()) else {
+    let Some(total_bytes) = values.len().checked_mul(size_of::<T>()) else {
         return null_mut();
     };
 
-    let ptr = alloc_bytes(total_bytes) as *mut u32;
+    let ptr = alloc_bytes(total_bytes) as *mut T;
     if ptr.is_null() {
         return null_mut();
     }
 
-    for (i, &(r, g, b, pop)) in colors.iter().enumerate() {
-        unsafe {
-            *ptr.add(i * 4) = r;
-            *ptr.add(i * 4 + 1) = g;
-            *ptr.add(i * 4 + 2) = b;
-            *ptr.add(i * 4 + 3) = pop;
-        }
+    // SAFETY: destination has enough space for values.len() elements.
+    unsafe {
+        ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
     }
 
     ptr
 }
 
-#[inline]
-fn color_distance_sq(r1: u32, g1: u32, b1: u32, r2: u32, g2: u32, b2: u32) -> u32 {
-    let dr = r1.wrapping_sub(r2);
-    let dg = g1.wrapping_sub(g2);
-    let db = b1.wrapping_sub(b2);
-    // Use i32 to handle negative differences correctly
-    let dr = dr as i32;
-    let dg = dg as i32;
-    let db = db as i32;
-    (dr * dr + dg * dg + db * db) as u32
+/// Slices image `index` out of a batch buffer described by `[width, height]` pairs and
+/// byte offsets.
+fn batch_image<'a>(
+    images_data: *const u8,
+    dims: &[i32],
+    offsets: &[i32],
+    index: usize,
+) -> Result<(&'a [u8], usize, usize), &'static str> {
+    let width = dims[index * 2];
+    let height = dims[index * 2 + 1];
+    let offset = offsets[index];
+
+    if width <= 0 || height <= 0 || offset < 0 {
+        return Err("Invalid image metadata");
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+    let image_len = width
+        .checked_mul(height)
+        .and_then(|v| v.checked_mul(4))
+        .ok_or("Image size overflow")?;
+
+    // SAFETY: caller guarantees images_data has image_len bytes from offset.
+    let image = unsafe { core::slice::from_raw_parts(images_data.add(offset as usize), image_len) };
+    Ok((image, width, height))
 }
 
-/// Extract RGB pixels from RGBA data, skipping transparent pixels.
-fn extract_rgb_pixels(pixel_data: &[u8], skip_alpha: u8) -> Vec<(u32, u32, u32)> {
-    let num_pixels = pixel_data.len() / 4;
-    let mut pixels = Vec::with_capacity(num_pixels);
+/// Validates a single-image FFI call and runs `hasher` over the caller's RGBA buffer.
+fn hash_rgba_export(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    hash_size: i32,
+    hasher: impl FnOnce(&[u8], usize, usize, usize) -> Result<String, &'static str>,
+) -> *mut HashResult {
+    if image_data.is_null() || width <= 0 || height <= 0 || hash_size <= 0 {
+        return create_error_result("Invalid input parameters");
+    }
 
-    for i in 0..num_pixels {
-        let a = pixel_data[i * 4 + 3];
-        if a < skip_alpha {
-            continue;
+    let width_usize = width as usize;
+    let height_usize = height as usize;
+
+    let Some(image_len) = width_usize
+        .checked_mul(height_usize)
+        .and_then(|v| v.checked_mul(4))
+    else {
+        return create_error_result("Image size overflow");
+    };
+
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image_slice = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+
+    match hasher(image_slice, width_usize, height_usize, hash_size as usize) {
+        Ok(hash) => {
+            let hash_ptr = alloc_c_string(&hash);
+            if hash_ptr.is_null() {
+                return create_error_result("Failed to allocate hash result");
+            }
+            create_hash_result(hash_ptr, 0, null_mut())
         }
-        let r = pixel_data[i * 4] as u32;
-        let g = pixel_data[i * 4 + 1] as u32;
-        let b = pixel_data[i * 4 + 2] as u32;
-        pixels.push((r, g, b));
+        Err(error) => create_error_result(error),
     }
+}
 
-    pixels
+/// Average hash with the default preprocessing; `calculate_hash_with_options` takes the
+/// alpha, luma and trim settings.
+#[no_mangle]
+pub extern "C" fn calculate_perceptual_hash(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    hash_size: i32,
+) -> *mut HashResult {
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        calculate_hash_for_rgba,
+    )
 }
 
-/// K-Means clustering on RGBA pixel data.
+/// DCT-based pHash: area-downscale to `hash_size * 4` square luma, 2D DCT-II, keep the
+/// `hash_size x hash_size` low-frequency block and threshold it on its median.
+/// `hash_size = 8` gives the classic 64-bit pHash over a 32x32 image. Uses the default
+/// preprocessing, like the other per-algorithm exports.
 #[no_mangle]
-pub extern "C" fn kmeans_quantize(
-    pixel_data: *const u8,
+pub extern "C" fn calculate_phash(
+    image_data: *const u8,
     width: i32,
     height: i32,
-    k: i32,
-    max_iterations: i32,
-    skip_alpha_threshold: u8,
-) -> *mut ColorResult {
-    if pixel_data.is_null() || width <= 0 || height <= 0 || k <= 0 {
-        return create_color_error("Invalid input parameters");
-    }
+    hash_size: i32,
+) -> *mut HashResult {
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        calculate_dct_hash_for_rgba,
+    )
+}
 
-    let w = width as usize;
-    let h = height as usize;
-    let k = k as usize;
-    let max_iter = if max_iterations <= 0 { 20 } else { max_iterations as usize };
+/// Gradient dHash. Emits `hash_size²` row-gradient bits, followed by another
+/// `hash_size²` column-gradient bits when `include_columns` is non-zero.
+#[no_mangle]
+pub extern "C" fn calculate_difference_hash(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    hash_size: i32,
+    include_columns: i32,
+) -> *mut HashResult {
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        |data, width, height, hash_size| {
+            calculate_difference_hash_for_rgba(data, width, height, hash_size, include_columns != 0)
+        },
+    )
+}
 
-    let Some(num_pixels) = w.checked_mul(h) else {
-        return create_color_error("Pixel count overflow");
+/// Haar-wavelet wHash. `level` is the number of decomposition steps between the working
+/// image and the `hash_size x hash_size` band (`<= 0` picks the deepest level the source
+/// resolution supports); `remove_low_band` zeroes the coarsest approximation first.
+#[no_mangle]
+pub extern "C" fn calculate_wavelet_hash(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    hash_size: i32,
+    level: i32,
+    remove_low_band: i32,
+) -> *mut HashResult {
+    let level = if level > 0 {
+        Some(level as usize)
+    } else {
+        None
     };
-    let Some(data_len) = num_pixels.checked_mul(4) else {
-        return create_color_error("Data size overflow");
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        |data, width, height, hash_size| {
+            calculate_wavelet_hash_for_rgba(
+                data,
+                width,
+                height,
+                hash_size,
+                level,
+                remove_low_band != 0,
+            )
+        },
+    )
+}
+
+/// Hashes with `algorithm` (a `HashAlgorithm` id) and explicit preprocessing, read from
+/// `options`, a block of 5 i32s (null for the defaults):
+///
+/// - `alpha_mode`: 0 ignores alpha like the per-algorithm exports, 1 composites onto
+///   `background_rgb` so transparent images hash the same whatever colour their invisible
+///   pixels hold, and 2 also appends a hash of the alpha plane (twice the bits).
+/// - `background_rgb`: compositing background as 0xRRGGBB.
+/// - `luma_model`: 0 averages R, G and B like the per-algorithm exports; 1 is BT.601, 2 is
+///   BT.709 and 3 is BT.709 on linear light.
+/// - `trim_alpha_threshold`, `trim_tolerance`: borders are trimmed first as in
+///   `trim_image`; -1 disables either check. Trimming is opt-in: the defaults, the
+///   per-algorithm exports, `calculate_batch_hashes` and the untrimmed quantize exports
+///   never trim.
+///
+/// Non-default luma, alpha and trim settings are recorded in the tag, e.g.
+/// `v1:ahash+luma=bt709+alpha=composite-ffffff+trim=0-8:64:<hex>`, so hashes made with
+/// different settings never compare as compatible. Every other option-taking export reads the same
+/// block. Free with `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_hash_with_options(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let Some(algorithm) = HashAlgorithm::from_id(algorithm) else {
+        return create_error_result("Unknown hash algorithm");
+    };
+    let options = match read_preprocess_options(options) {
+        Ok(options) => options,
+        Err(message) => return create_error_result(message),
     };
 
-    let data = unsafe { core::slice::from_raw_parts(pixel_data, data_len) };
-    let pixels = extract_rgb_pixels(data, skip_alpha_threshold);
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        |data, width, height, hash_size| {
+            calculate_hash_with_options_for_rgba(
+                data, width, height, algorithm, hash_size, &options,
+            )
+        },
+    )
+}
 
-    if pixels.is_empty() {
-        return create_color_result(null_mut(), 0, 0, null_mut());
+/// One element of a `HashResult` array (see `free_batch_results`).
+fn hash_result_entry(hashed: Result<String, &str>) -> HashResult {
+    match hashed {
+        Ok(hash) => {
+            let hash_ptr = alloc_c_string(&hash);
+            if hash_ptr.is_null() {
+                HashResult {
+                    hash: null_mut(),
+                    error: 1,
+                    error_message: alloc_c_string("Failed to allocate hash"),
+                }
+            } else {
+                HashResult {
+                    hash: hash_ptr,
+                    error: 0,
+                    error_message: null_mut(),
+                }
+            }
+        }
+        Err(error) => HashResult {
+            hash: null_mut(),
+            error: 1,
+            error_message: alloc_c_string(error),
+        },
     }
+}
 
-    if pixels.len() <= k {
-        let colors: Vec<(u32, u32, u32, u32)> = pixels.iter().map(|&(r, g, b)| (r, g, b, 1)).collect();
-        let ptr = alloc_color_array(&colors);
-        return create_color_result(ptr, colors.len() as i32, 0, null_mut());
+/// Average hashes of a batch with the default preprocessing; see
+/// `calculate_batch_hashes_with_options`.
+#[no_mangle]
+pub extern "C" fn calculate_batch_hashes(
+    images_data: *const u8,
+    dimensions: *const i32,
+    image_offsets: *const i32,
+    num_images: i32,
+    hash_size: i32,
+) -> *mut HashResult {
+    if images_data.is_null()
+        || dimensions.is_null()
+        || image_offsets.is_null()
+        || num_images <= 0
+        || hash_size <= 0
+    {
+        return null_mut();
     }
 
-    // Initialize centroids by evenly sampling the pixel array (deterministic)
-    let mut centroids: Vec<(u32, u32, u32)> = Vec::with_capacity(k);
-    for i in 0..k {
-        let idx = (i * pixels.len()) / k;
-        centroids.push(pixels[idx]);
+    let num = num_images as usize;
+    let total_bytes = match num.checked_mul(size_of::<HashResult>()) {
+        Some(size) => size,
+        None => return null_mut(),
+    };
+
+    let results_ptr = alloc_bytes(total_bytes) as *mut HashResult;
+    if results_ptr.is_null() {
+        return null_mut();
     }
 
-    // Cluster assignment buffer
-    let mut assignments = vec![0usize; pixels.len()];
-    let mut cluster_counts = vec![0u64; k];
-    let mut cluster_sums_r = vec![0u64; k];
-    let mut cluster_sums_g = vec![0u64; k];
-    let mut cluster_sums_b = vec![0u64; k];
+    // SAFETY: pointers are valid and lengths are controlled by caller.
+    let dims = unsafe { core::slice::from_raw_parts(dimensions, num * 2) };
+    let offsets = unsafe { core::slice::from_raw_parts(image_offsets, num) };
 
-    for _iter in 0..max_iter {
-        // Assign each pixel to nearest centroid
-        for (pi, &(r, g, b)) in pixels.iter().enumerate() {
-            let mut min_dist = u32::MAX;
-            let mut min_idx = 0usize;
-            for (ci, &(cr, cg, cb)) in centroids.iter().enumerate() {
-                let dist = color_distance_sq(r, g, b, cr, cg, cb);
-                if dist < min_dist {
-                    min_dist = dist;
-                    min_idx = ci;
-                }
-            }
-            assignments[pi] = min_idx;
-        }
+    for i in 0..num {
+        let hashed = batch_image(images_data, dims, offsets, i)
+            .and_then(|(image, w, h)| calculate_hash_for_rgba(image, w, h, hash_size as usize));
 
-        // Recompute centroids
-        for i in 0..k {
-            cluster_counts[i] = 0;
-            cluster_sums_r[i] = 0;
-            cluster_sums_g[i] = 0;
-            cluster_sums_b[i] = 0;
+        // SAFETY: results_ptr points to an array of num HashResult entries.
+        unsafe {
+            results_ptr.add(i).write(hash_result_entry(hashed));
         }
+    }
 
-        for (pi, &(r, g, b)) in pixels.iter().enumerate() {
-            let ci = assignments[pi];
-            cluster_counts[ci] += 1;
+    results_ptr
+}
+
+/// Average hashes for a batch laid out like `calculate_batch_hashes`, written straight into
+/// one packed block buffer (`num_images x ceil(hash_size² / 64)` u64s) instead of strings.
+/// `out_status[i]` is 0 on success and 1 when image `i` failed (its blocks are zeroed).
+/// `out_words`/`out_bits` receive the per-hash layout as in `pack_hashes`. Uses the default
+/// preprocessing; see `calculate_batch_hashes_packed_with_options`.
+#[no_mangle]
+pub extern "C" fn calculate_batch_hashes_packed(
+    images_data: *const u8,
+    dimensions: *const i32,
+    image_offsets: *const i32,
+    num_images: i32,
+    hash_size: i32,
+    out_status: *mut i32,
+    out_words: *mut i32,
+    out_bits: *mut i32,
+) -> *mut u64 {
+    write_out_i32(out_words, 0);
+    write_out_i32(out_bits, 0);
+
+    if images_data.is_null()
+        || dimensions.is_null()
+        || image_offsets.is_null()
+        || out_status.is_null()
+        || num_images <= 0
+        || check_hash_size(hash_size.max(0) as usize).is_err()
+    {
+        return null_mut();
+    }
+
+    let num = num_images as usize;
+    let hash_size = hash_size as usize;
+    let bit_length = hash_size * hash_size;
+    let words = bit_length.div_ceil(64);
+
+    // SAFETY: pointers are valid and lengths are controlled by caller.
+    let dims = unsafe { core::slice::from_raw_parts(dimensions, num * 2) };
+    let offsets = unsafe { core::slice::from_raw_parts(image_offsets, num) };
+    let status = unsafe { core::slice::from_raw_parts_mut(out_status, num) };
+
+    let mut blocks = vec![0u64; num * words];
+    for i in 0..num {
+        let hashed = batch_image(images_data, dims, offsets, i)
+            .and_then(|(image, w, h)| rgba_to_gray(image, w, h));
+
+        status[i] = match hashed {
+            Ok(gray) => {
+                let bits = average_hash_bits(&gray, hash_size);
+                bits_to_blocks(&bits, &mut blocks[i * words..(i + 1) * words]);
+                0
+            }
+            Err(_) => 1,
+        };
+    }
+
+    write_out_i32(out_words, words as i32);
+    write_out_i32(out_bits, bit_length as i32);
+    alloc_array(&blocks)
+}
+
+/// `calculate_batch_hashes` with any `algorithm` and a preprocessing `options` block as in
+/// `calculate_hash_with_options`. Free with `free_batch_results`.
+#[no_mangle]
+pub extern "C" fn calculate_batch_hashes_with_options(
+    images_data: *const u8,
+    dimensions: *const i32,
+    image_offsets: *const i32,
+    num_images: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    if images_data.is_null()
+        || dimensions.is_null()
+        || image_offsets.is_null()
+        || num_images <= 0
+        || hash_size <= 0
+    {
+        return null_mut();
+    }
+    let hashed_with = match (
+        HashAlgorithm::from_id(algorithm),
+        read_preprocess_options(options),
+    ) {
+        (Some(algorithm), Ok(options)) => Ok((algorithm, options)),
+        (None, _) => Err("Unknown hash algorithm"),
+        (_, Err(message)) => Err(message),
+    };
+
+    let num = num_images as usize;
+    let Some(total_bytes) = num.checked_mul(size_of::<HashResult>()) else {
+        return null_mut();
+    };
+    let results_ptr = alloc_bytes(total_bytes) as *mut HashResult;
+    if results_ptr.is_null() {
+        return null_mut();
+    }
+
+    // SAFETY: pointers are valid and lengths are controlled by caller.
+    let dims = unsafe { core::slice::from_raw_parts(dimensions, num * 2) };
+    let offsets = unsafe { core::slice::from_raw_parts(image_offsets, num) };
+
+    for i in 0..num {
+        let hashed = hashed_with.and_then(|(algorithm, options)| {
+            let (image, w, h) = batch_image(images_data, dims, offsets, i)?;
+            calculate_hash_with_options_for_rgba(
+                image,
+                w,
+                h,
+                algorithm,
+                hash_size as usize,
+                &options,
+            )
+        });
+
+        // SAFETY: results_ptr points to an array of num HashResult entries.
+        unsafe {
+            results_ptr.add(i).write(hash_result_entry(hashed));
+        }
+    }
+
+    results_ptr
+}
+
+/// `calculate_batch_hashes_packed` with any `algorithm` and a preprocessing `options` block
+/// as in `calculate_hash_with_options`. Writes the words per hash and the bit length (as
+/// `pack_hashes` does) to `out_words` and `out_bits`; returns null on invalid input.
+#[no_mangle]
+pub extern "C" fn calculate_batch_hashes_packed_with_options(
+    images_data: *const u8,
+    dimensions: *const i32,
+    image_offsets: *const i32,
+    num_images: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+    out_status: *mut i32,
+    out_words: *mut i32,
+    out_bits: *mut i32,
+) -> *mut u64 {
+    write_out_i32(out_words, 0);
+    write_out_i32(out_bits, 0);
+
+    let Some(algorithm) = HashAlgorithm::from_id(algorithm) else {
+        return null_mut();
+    };
+    let Ok(options) = read_preprocess_options(options) else {
+        return null_mut();
+    };
+    if images_data.is_null()
+        || dimensions.is_null()
+        || image_offsets.is_null()
+        || out_status.is_null()
+        || num_images <= 0
+        || check_hash_size(hash_size.max(0) as usize).is_err()
+    {
+        return null_mut();
+    }
+
+    let num = num_images as usize;
+    let hash_size = hash_size as usize;
+    let bits = hash_bit_count(algorithm, hash_size, options.gray.alpha);
+    let words = bits.div_ceil(64);
+
+    // SAFETY: pointers are valid and lengths are controlled by caller.
+    let dims = unsafe { core::slice::from_raw_parts(dimensions, num * 2) };
+    let offsets = unsafe { core::slice::from_raw_parts(image_offsets, num) };
+    let status = unsafe { core::slice::from_raw_parts_mut(out_status, num) };
+
+    let mut blocks = vec![0u64; num * words];
+    for i in 0..num {
+        let hashed = batch_image(images_data, dims, offsets, i).and_then(|(image, w, h)| {
+            preprocessed_hash_bits(image, w, h, algorithm, hash_size, &options)
+        });
+
+        status[i] = match hashed {
+            Ok(hash_bits) => {
+                bits_to_blocks(&hash_bits, &mut blocks[i * words..(i + 1) * words]);
+                0
+            }
+            Err(_) => 1,
+        };
+    }
+
+    write_out_i32(out_words, words as i32);
+    write_out_i32(out_bits, bits as i32);
+    alloc_array(&blocks)
+}
+
+#[no_mangle]
+pub extern "C" fn calculate_hamming_distance(hash1: *const u8, hash2: *const u8) -> i32 {
+    let packed1 = parse_packed_hash(hash1);
+    let packed2 = parse_packed_hash(hash2);
+    hamming_distance_packed(&packed1, &packed2, -1)
+}
+
+#[no_mangle]
+pub extern "C" fn hash_format_version() -> i32 {
+    HASH_FORMAT_VERSION as i32
+}
+
+/// Writes `[format version, algorithm id, bit length]` for `hash` into `out_info`
+/// (3 i32s). Bare hex hashes report version 0 and algorithm 0. Returns 0 on success or
+/// a negative `HASH_ERROR_*` code, so cached hashes can be checked before comparing.
+/// Hashes with equal info can still differ in preprocessing; see
+/// `get_hash_preprocessing`.
+#[no_mangle]
+pub extern "C" fn get_hash_info(hash: *const u8, out_info: *mut i32) -> i32 {
+    if out_info.is_null() {
+        return HASH_ERROR_INVALID;
+    }
+
+    let packed = parse_packed_hash(hash);
+    let status = if packed.version > HASH_FORMAT_VERSION {
+        HASH_ERROR_UNSUPPORTED_VERSION
+    } else if !packed.valid {
+        HASH_ERROR_INVALID
+    } else {
+        0
+    };
+
+    // SAFETY: caller guarantees out_info points to 3 writable i32s.
+    unsafe {
+        *out_info = packed.version as i32;
+        *out_info.add(1) = packed.algorithm as i32;
+        *out_info.add(2) = packed.bits as i32;
+    }
+
+    status
+}
+
+/// Writes the preprocessing recorded in `hash`'s tag into `out_options`, as an options
+/// block of 5 i32s in the layout `calculate_hash_with_options` reads, so a cached hash can
+/// be checked against the current settings (and recomputed with them) before comparing.
+/// Bare hex hashes report the defaults. Returns 0 or a negative `HASH_ERROR_*` code, like
+/// `get_hash_info`.
+#[no_mangle]
+pub extern "C" fn get_hash_preprocessing(hash: *const u8, out_options: *mut i32) -> i32 {
+    if out_options.is_null() {
+        return HASH_ERROR_INVALID;
+    }
+
+    let packed = parse_packed_hash(hash);
+    let status = if packed.version > HASH_FORMAT_VERSION {
+        HASH_ERROR_UNSUPPORTED_VERSION
+    } else if !packed.valid {
+        HASH_ERROR_INVALID
+    } else {
+        0
+    };
+
+    let options = packed.options;
+    // SAFETY: caller guarantees out_options points to PREPROCESS_OPTION_FIELDS writable i32s.
+    let out = unsafe { core::slice::from_raw_parts_mut(out_options, PREPROCESS_OPTION_FIELDS) };
+    out.copy_from_slice(&options.to_fields());
+
+    status
+}
+
+/// Migrates a bare hex hash produced by `algorithm` into the tagged format.
+/// `bits <= 0` takes the full payload (4 bits per hex digit).
+#[no_mangle]
+pub extern "C" fn tag_legacy_hash(hash: *const u8, algorithm: i32, bits: i32) -> *mut HashResult {
+    let Some(algorithm) = HashAlgorithm::from_id(algorithm) else {
+        return create_error_result("Unknown hash algorithm");
+    };
+
+    let Some(bytes) = parse_c_hex(hash) else {
+        return create_error_result("Invalid input parameters");
+    };
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_hexdigit) {
+        return create_error_result("Hash is not a bare hex string");
+    }
+
+    let payload_bits = bytes.len() * 4;
+    let bits = if bits <= 0 {
+        payload_bits
+    } else {
+        bits as usize
+    };
+    if bits > payload_bits || bits.div_ceil(4) != bytes.len() {
+        return create_error_result("Bit length does not match hash payload");
+    }
+
+    let hash_bits: Vec<bool> = bytes
+        .iter()
+        .flat_map(|&ch| {
+            let value = hex_to_val(ch);
+            (0..4).rev().map(move |shift| (value >> shift) & 1 == 1)
+        })
+        .take(bits)
+        .collect();
+
+    let hash_ptr = alloc_c_string(&encode_hash(algorithm, &hash_bits));
+    if hash_ptr.is_null() {
+        return create_error_result("Failed to allocate hash result");
+    }
+    create_hash_result(hash_ptr, 0, null_mut())
+}
+
+fn write_out_i32(out_count: *mut i32, value: i32) {
+    if !out_count.is_null() {
+        // SAFETY: out_count points to writable memory.
+        unsafe {
+            *out_count = value;
+        }
+    }
+}
+
+/// Emits `pairs` (flattened `[i, j]`) as an FFI array and reports the pair count.
+fn pairs_output(pairs: &[i32], out_count: *mut i32) -> *mut i32 {
+    write_out_i32(out_count, (pairs.len() / 2) as i32);
+    alloc_array(pairs)
+}
+
+/// Emits flattened `[i, j, distance]` matches and reports the match count. Without
+/// `with_distances` the distance column is dropped, leaving the classic `[i, j]` layout.
+fn matches_output(matches: &[i32], with_distances: bool, out_count: *mut i32) -> *mut i32 {
+    if with_distances {
+        write_out_i32(out_count, (matches.len() / 3) as i32);
+        return alloc_array(matches);
+    }
+
+    let pairs: Vec<i32> = matches.chunks_exact(3).flat_map(|m| [m[0], m[1]]).collect();
+    pairs_output(&pairs, out_count)
+}
+
+/// All-pairs scan returning flattened `[i, j, distance]` matches. `distance(i, j,
+/// early_stop)` returns a negative code for pairs that cannot be compared.
+fn collect_similar_pairs(
+    num: usize,
+    threshold: i32,
+    distance: impl Fn(usize, usize, i32) -> i32,
+) -> Vec<i32> {
+    let mut pairs: Vec<i32> = Vec::new();
+    let threshold_for_early_stop = threshold.max(0);
+
+    for i in 0..num {
+        for j in (i + 1)..num {
+            let d = distance(i, j, threshold_for_early_stop);
+            if d >= 0 && d <= threshold {
+                pairs.extend_from_slice(&[i as i32, j as i32, d]);
+            }
+        }
+    }
+
+    pairs
+}
+
+fn bucket_range(start: i32, size: i32, num: usize) -> Option<core::ops::Range<usize>> {
+    if start < 0 || size <= 0 || start as usize >= num {
+        return None;
+    }
+
+    let start = start as usize;
+    Some(start..start.saturating_add(size as usize).min(num))
+}
+
+/// Compares within each caller-provided bucket and across adjacent buckets.
+fn collect_bucketed_pairs(
+    num: usize,
+    starts: &[i32],
+    sizes: &[i32],
+    threshold: i32,
+    distance: impl Fn(usize, usize, i32) -> i32,
+) -> Vec<i32> {
+    let mut pairs: Vec<i32> = Vec::new();
+    let threshold_for_early_stop = threshold.max(0);
+    let mut check = |i: usize, j: usize| {
+        let d = distance(i, j, threshold_for_early_stop);
+        if d >= 0 && d <= threshold {
+            pairs.extend_from_slice(&[i as i32, j as i32, d]);
+        }
+    };
+
+    // Compare within each bucket.
+    for b in 0..starts.len() {
+        let Some(range) = bucket_range(starts[b], sizes[b], num) else {
+            continue;
+        };
+
+        for i in range.clone() {
+            for j in (i + 1)..range.end {
+                check(i, j);
+            }
+        }
+    }
+
+    // Compare adjacent buckets to catch near-prefix matches.
+    for b in 1..starts.len() {
+        let (Some(range1), Some(range2)) = (
+            bucket_range(starts[b - 1], sizes[b - 1], num),
+            bucket_range(starts[b], sizes[b], num),
+        ) else {
+            continue;
+        };
+
+        for i in range1 {
+            for j in range2.clone() {
+                check(i, j);
+            }
+        }
+    }
+
+    pairs
+}
+
+fn parse_hash_array(hashes: *const *const u8, num: usize) -> Vec<PackedHash> {
+    // SAFETY: hashes points to num pointers.
+    let hash_ptrs = unsafe { core::slice::from_raw_parts(hashes, num) };
+    hash_ptrs
+        .iter()
+        .map(|&ptr| parse_packed_hash(ptr))
+        .collect()
+}
+
+fn find_similar_pairs_impl(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if hashes.is_null() || num_hashes <= 1 {
+        return null_mut();
+    }
+
+    let num = num_hashes as usize;
+    let parsed_hashes = parse_hash_array(hashes, num);
+
+    let pairs = collect_similar_pairs(num, threshold, |i, j, early_stop| {
+        hamming_distance_packed(&parsed_hashes[i], &parsed_hashes[j], early_stop)
+    });
+
+    matches_output(&pairs, with_distances, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_similar_pairs(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_impl(hashes, num_hashes, threshold, false, out_count)
+}
+
+/// Like `find_similar_pairs`, but returns `[i, j, distance]` triples so callers can rank
+/// matches without recomputing distances. Free with `free_pairs`.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_with_distances(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_impl(hashes, num_hashes, threshold, true, out_count)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn find_similar_pairs_bucketed_impl(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if hashes.is_null() || bucket_starts.is_null() || bucket_sizes.is_null() {
+        return null_mut();
+    }
+
+    if num_hashes <= 1 || num_buckets <= 0 {
+        return null_mut();
+    }
+
+    let num_hashes_usize = num_hashes as usize;
+    let num_buckets_usize = num_buckets as usize;
+
+    // SAFETY: caller guarantees valid arrays with these lengths.
+    let starts = unsafe { core::slice::from_raw_parts(bucket_starts, num_buckets_usize) };
+    let sizes = unsafe { core::slice::from_raw_parts(bucket_sizes, num_buckets_usize) };
+
+    let parsed_hashes = parse_hash_array(hashes, num_hashes_usize);
+
+    let pairs = collect_bucketed_pairs(
+        num_hashes_usize,
+        starts,
+        sizes,
+        threshold,
+        |i, j, early_stop| {
+            hamming_distance_packed(&parsed_hashes[i], &parsed_hashes[j], early_stop)
+        },
+    );
+
+    matches_output(&pairs, with_distances, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_bucketed(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_bucketed_impl(
+        hashes,
+        num_hashes,
+        bucket_starts,
+        bucket_sizes,
+        num_buckets,
+        threshold,
+        false,
+        out_count,
+    )
+}
+
+/// `find_similar_pairs_bucketed` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_bucketed_with_distances(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_bucketed_impl(
+        hashes,
+        num_hashes,
+        bucket_starts,
+        bucket_sizes,
+        num_buckets,
+        threshold,
+        true,
+        out_count,
+    )
+}
+
+// ===== Packed Hash Blocks =====
+//
+// Binary counterparts of the string exports above. A hash set is one contiguous buffer of
+// `num_hashes x words_per_hash` u64 blocks, each hash packed MSB-first exactly like the
+// payload of its hex string, so no per-hash string marshalling is needed on either side.
+
+/// Packs hash strings into one block buffer. All hashes must parse and share the same
+/// algorithm and bit length; `out_words`/`out_bits` receive the per-hash layout. Returns
+/// null (with `*out_words = 0`) when the set is empty or inconsistent.
+#[no_mangle]
+pub extern "C" fn pack_hashes(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    out_words: *mut i32,
+    out_bits: *mut i32,
+) -> *mut u64 {
+    write_out_i32(out_words, 0);
+    write_out_i32(out_bits, 0);
+
+    if hashes.is_null() || num_hashes <= 0 {
+        return null_mut();
+    }
+
+    let parsed_hashes = parse_hash_array(hashes, num_hashes as usize);
+    let first = &parsed_hashes[0];
+    if parsed_hashes
+        .iter()
+        .any(|hash| hash_compatibility(first, hash).is_err())
+    {
+        return null_mut();
+    }
+
+    let words = first.blocks.len();
+    let mut blocks = Vec::with_capacity(parsed_hashes.len() * words);
+    for hash in &parsed_hashes {
+        blocks.extend_from_slice(&hash.blocks);
+    }
+
+    write_out_i32(out_words, words as i32);
+    write_out_i32(out_bits, first.bits as i32);
+    alloc_array(&blocks)
+}
+
+#[no_mangle]
+pub extern "C" fn calculate_hamming_distance_packed(
+    hash1: *const u64,
+    hash2: *const u64,
+    words_per_hash: i32,
+) -> i32 {
+    let (Some(hash1), Some(hash2)) = (
+        HashBlocks::from_raw(hash1, 1, words_per_hash),
+        HashBlocks::from_raw(hash2, 1, words_per_hash),
+    ) else {
+        return HASH_ERROR_INVALID;
+    };
+
+    hamming_distance_words(hash1.get(0), hash2.get(0), -1)
+}
+
+fn find_similar_pairs_packed_impl(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if num_hashes <= 1 {
+        return null_mut();
+    }
+    let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+        return null_mut();
+    };
+
+    let pairs = collect_similar_pairs(hashes.len(), threshold, |i, j, early_stop| {
+        hamming_distance_words(hashes.get(i), hashes.get(j), early_stop)
+    });
+
+    matches_output(&pairs, with_distances, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_packed(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        threshold,
+        false,
+        out_count,
+    )
+}
+
+/// `find_similar_pairs_packed` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_packed_with_distances(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        threshold,
+        true,
+        out_count,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn find_similar_pairs_bucketed_packed_impl(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if bucket_starts.is_null() || bucket_sizes.is_null() || num_hashes <= 1 || num_buckets <= 0 {
+        return null_mut();
+    }
+    let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+        return null_mut();
+    };
+
+    let num_buckets_usize = num_buckets as usize;
+    // SAFETY: caller guarantees valid arrays with these lengths.
+    let starts = unsafe { core::slice::from_raw_parts(bucket_starts, num_buckets_usize) };
+    let sizes = unsafe { core::slice::from_raw_parts(bucket_sizes, num_buckets_usize) };
+
+    let pairs = collect_bucketed_pairs(
+        hashes.len(),
+        starts,
+        sizes,
+        threshold,
+        |i, j, early_stop| hamming_distance_words(hashes.get(i), hashes.get(j), early_stop),
+    );
+
+    matches_output(&pairs, with_distances, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_bucketed_packed(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_bucketed_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        bucket_starts,
+        bucket_sizes,
+        num_buckets,
+        threshold,
+        false,
+        out_count,
+    )
+}
+
+/// `find_similar_pairs_bucketed_packed` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_bucketed_packed_with_distances(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bucket_starts: *const i32,
+    bucket_sizes: *const i32,
+    num_buckets: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_bucketed_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        bucket_starts,
+        bucket_sizes,
+        num_buckets,
+        threshold,
+        true,
+        out_count,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn free_hash_blocks(blocks: *mut u64) {
+    dealloc_bytes(blocks as *mut u8);
+}
+
+#[no_mangle]
+pub extern "C" fn free_hash_result(result: *mut HashResult) {
+    if result.is_null() {
+        return;
+    }
+
+    // SAFETY: result is a valid pointer returned by create_hash_result.
+    unsafe {
+        let value = result.read();
+        if !value.hash.is_null() {
+            dealloc_bytes(value.hash);
+        }
+        if !value.error_message.is_null() {
+            dealloc_bytes(value.error_message);
+        }
+    }
+
+    dealloc_bytes(result as *mut u8);
+}
+
+#[no_mangle]
+pub extern "C" fn free_batch_results(results: *mut HashResult, num_results: i32) {
+    if results.is_null() || num_results <= 0 {
+        return;
+    }
+
+    let len = num_results as usize;
+
+    for i in 0..len {
+        // SAFETY: results points to an array of len HashResult entries.
+        let entry = unsafe { results.add(i).read() };
+        if !entry.hash.is_null() {
+            dealloc_bytes(entry.hash);
+        }
+        if !entry.error_message.is_null() {
+            dealloc_bytes(entry.error_message);
+        }
+    }
+
+    dealloc_bytes(results as *mut u8);
+}
+
+#[no_mangle]
+pub extern "C" fn free_pairs(pairs: *mut i32) {
+    dealloc_bytes(pairs as *mut u8);
+}
+
+#[no_mangle]
+pub extern "C" fn has_simd_support() -> i32 {
+    if cfg!(target_feature = "simd128") {
+        1
+    } else {
+        0
+    }
+}
+
+// ===== Multi-Index Hashing =====
+//
+// Exact thresholded search by the pigeonhole principle: split every hash into
+// `threshold + 1` disjoint segments; two hashes within `threshold` bits of each other must
+// agree exactly on at least one segment. Each segment gets a sorted table, so candidates
+// are only the hashes sharing a segment value, and every candidate is verified in full.
+
+/// Below this many bits per segment, segment tables stop filtering and a full scan wins.
+const MIN_MIH_SEGMENT_BITS: usize = 8;
+
+/// Reads `len <= 64` bits starting at bit `start` (MSB-first), right-aligned.
+fn extract_bits(hash: &[u64], start: usize, len: usize) -> u64 {
+    let word = start / 64;
+    let offset = start % 64;
+    let mut value = hash[word] << offset;
+    if offset != 0 && word + 1 < hash.len() {
+        value |= hash[word + 1] >> (64 - offset);
+    }
+    if len < 64 {
+        value >> (64 - len)
+    } else {
+        value
+    }
+}
+
+/// Number of leading bits that carry data in any of `hashes`. Packed blocks do not record
+/// their bit length, but padding is zero in every hash, so trailing all-zero columns can be
+/// dropped without changing any distance.
+fn effective_bits(hashes: &HashBlocks) -> usize {
+    let mut combined = vec![0u64; hashes.words];
+    for i in 0..hashes.len() {
+        for (acc, &block) in combined.iter_mut().zip(hashes.get(i)) {
+            *acc |= block;
+        }
+    }
+
+    match combined.iter().rposition(|&block| block != 0) {
+        Some(word) => word * 64 + 64 - combined[word].trailing_zeros() as usize,
+        None => 0,
+    }
+}
+
+struct MultiIndex<'a> {
+    hashes: HashBlocks<'a>,
+    /// `(start bit, bit length)` of each segment.
+    segments: Vec<(usize, usize)>,
+    /// Per segment: `(segment key, item index)` sorted by key.
+    tables: Vec<Vec<(u64, u32)>>,
+}
+
+impl<'a> MultiIndex<'a> {
+    /// Indexes `hashes` for exact search up to `threshold`. Returns `None` when the
+    /// segments would be too short to filter anything; callers fall back to a full scan.
+    fn build(hashes: HashBlocks<'a>, threshold: i32) -> Option<Self> {
+        let bits = effective_bits(&hashes);
+        let count = threshold.max(0) as usize + 1;
+        if bits / count < MIN_MIH_SEGMENT_BITS {
+            return None;
+        }
+
+        let segments: Vec<(usize, usize)> = (0..count)
+            .map(|s| {
+                let start = s * bits / count;
+                (start, (s + 1) * bits / count - start)
+            })
+            .collect();
+
+        let tables = segments
+            .iter()
+            .map(|&segment| {
+                let mut table: Vec<(u64, u32)> = (0..hashes.len())
+                    .map(|i| (Self::key(hashes.get(i), segment), i as u32))
+                    .collect();
+                table.sort_unstable();
+                table
+            })
+            .collect();
+
+        Some(MultiIndex {
+            hashes,
+            segments,
+            tables,
+        })
+    }
+
+    /// Segment value, folded into 64 bits for segments longer than one word.
+    /// Folding may collide; `first_equal_segment` is the exact check.
+    fn key(hash: &[u64], (start, len): (usize, usize)) -> u64 {
+        if len <= 64 {
+            return extract_bits(hash, start, len);
+        }
+
+        let mut key = 0xcbf2_9ce4_8422_2325u64;
+        let mut offset = 0;
+        while offset < len {
+            let chunk = (len - offset).min(64);
+            key = (key ^ extract_bits(hash, start + offset, chunk)).wrapping_mul(0x1000_0000_01b3);
+            offset += chunk;
+        }
+        key
+    }
+
+    fn first_equal_segment(&self, hash1: &[u64], hash2: &[u64]) -> Option<usize> {
+        self.segments.iter().position(|&(start, len)| {
+            let mut offset = 0;
+            while offset < len {
+                let chunk = (len - offset).min(64);
+                if extract_bits(hash1, start + offset, chunk)
+                    != extract_bits(hash2, start + offset, chunk)
+                {
+                    return false;
+                }
+                offset += chunk;
+            }
+            true
+        })
+    }
+
+    /// Calls `visit` once for every indexed item that shares at least one segment with
+    /// `probe`.
+    fn for_each_candidate(&self, probe: &[u64], mut visit: impl FnMut(usize)) {
+        for (s, table) in self.tables.iter().enumerate() {
+            let key = Self::key(probe, self.segments[s]);
+            let from = table.partition_point(|&(k, _)| k < key);
+            for &(k, item) in &table[from..] {
+                if k != key {
+                    break;
+                }
+                // Only report the candidate from the first segment it matches on.
+                if self.first_equal_segment(probe, self.hashes.get(item as usize)) == Some(s) {
+                    visit(item as usize);
+                }
+            }
+        }
+    }
+}
+
+/// Exact equivalent of `collect_similar_pairs` over packed hashes, with matches in the same
+/// ascending `(i, j)` order, using a multi-index when the threshold allows it.
+fn collect_similar_pairs_mih(hashes: HashBlocks, threshold: i32) -> Vec<i32> {
+    if threshold < 0 {
+        return Vec::new();
+    }
+
+    let Some(index) = MultiIndex::build(hashes, threshold) else {
+        return collect_similar_pairs(hashes.len(), threshold, |i, j, early_stop| {
+            hamming_distance_words(hashes.get(i), hashes.get(j), early_stop)
+        });
+    };
+
+    let mut matches: Vec<(u32, u32, i32)> = Vec::new();
+    for i in 0..hashes.len() {
+        let hash = hashes.get(i);
+        index.for_each_candidate(hash, |j| {
+            if j <= i {
+                return;
+            }
+            let d = hamming_distance_words(hash, hashes.get(j), threshold);
+            if d <= threshold {
+                matches.push((i as u32, j as u32, d));
+            }
+        });
+    }
+
+    matches.sort_unstable();
+    matches
+        .into_iter()
+        .flat_map(|(i, j, d)| [i as i32, j as i32, d])
+        .collect()
+}
+
+/// Partitions valid parsed hashes into groups of mutually comparable ones (same format,
+/// algorithm and bit length), in order of first appearance.
+fn compatible_groups(parsed_hashes: &[PackedHash]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, hash) in parsed_hashes.iter().enumerate() {
+        if !hash.valid {
+            continue;
+        }
+        match groups
+            .iter_mut()
+            .find(|group| hash_compatibility(&parsed_hashes[group[0]], hash).is_ok())
+        {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+/// Copies the blocks of `group` into one contiguous buffer; returns it with words per hash.
+fn group_blocks(parsed_hashes: &[PackedHash], group: &[usize]) -> (Vec<u64>, usize) {
+    let words = parsed_hashes[group[0]].blocks.len();
+    let data = group
+        .iter()
+        .flat_map(|&i| parsed_hashes[i].blocks.iter().copied())
+        .collect();
+    (data, words)
+}
+
+/// Multi-index pair search over parsed strings. Only mutually comparable hashes can pair
+/// up, so each compatible group is indexed separately; pairs come back in `(i, j)` order.
+fn collect_similar_pairs_mih_parsed(
+    parsed_hashes: &[PackedHash],
+    threshold: impl Fn(usize) -> i32,
+) -> Vec<i32> {
+    let mut matches: Vec<(i32, i32, i32)> = Vec::new();
+    for group in compatible_groups(parsed_hashes)
+        .iter()
+        .filter(|group| group.len() > 1)
+    {
+        let (data, words) = group_blocks(parsed_hashes, group);
+        let group_threshold = threshold(parsed_hashes[group[0]].bits);
+        let pairs = collect_similar_pairs_mih(HashBlocks { data: &data, words }, group_threshold);
+        matches.extend(pairs.chunks_exact(3).map(|m| {
+            (
+                group[m[0] as usize] as i32,
+                group[m[1] as usize] as i32,
+                m[2],
+            )
+        }));
+    }
+
+    matches.sort_unstable();
+    matches
+        .into_iter()
+        .flat_map(|(i, j, d)| [i, j, d])
+        .collect()
+}
+
+fn find_similar_pairs_mih_impl(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if hashes.is_null() || num_hashes <= 1 {
+        return null_mut();
+    }
+
+    let parsed_hashes = parse_hash_array(hashes, num_hashes as usize);
+    let pairs = collect_similar_pairs_mih_parsed(&parsed_hashes, |_| threshold);
+    matches_output(&pairs, with_distances, out_count)
+}
+
+/// Exact thresholded pair search over hash strings via multi-index hashing. Returns the same
+/// pairs as `find_similar_pairs`, unlike the prefix heuristic of `find_similar_pairs_bucketed`.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_mih(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_mih_impl(hashes, num_hashes, threshold, false, out_count)
+}
+
+/// `find_similar_pairs_mih` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_mih_with_distances(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_mih_impl(hashes, num_hashes, threshold, true, out_count)
+}
+
+fn find_similar_pairs_mih_packed_impl(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if num_hashes <= 1 {
+        return null_mut();
+    }
+    let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+        return null_mut();
+    };
+
+    let pairs = collect_similar_pairs_mih(hashes, threshold);
+    matches_output(&pairs, with_distances, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_mih_packed(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_mih_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        threshold,
+        false,
+        out_count,
+    )
+}
+
+/// `find_similar_pairs_mih_packed` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_mih_packed_with_distances(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_mih_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        threshold,
+        true,
+        out_count,
+    )
+}
+
+/// Matches of every query against the corpus within `threshold`, as flattened
+/// `[query, corpus, distance]` triples grouped by query in input order. Each query's
+/// matches are sorted by distance then corpus index and cut to `top_k` when `top_k > 0`.
+fn collect_cross_matches(
+    queries: HashBlocks,
+    corpus: HashBlocks,
+    threshold: i32,
+    top_k: usize,
+) -> Vec<i32> {
+    let mut triples = Vec::new();
+    if threshold < 0 {
+        return triples;
+    }
+
+    let index = MultiIndex::build(corpus, threshold);
+    let mut matches: Vec<(i32, usize)> = Vec::new();
+    for q in 0..queries.len() {
+        let probe = queries.get(q);
+        matches.clear();
+
+        let mut check = |c: usize| {
+            let distance = hamming_distance_words(probe, corpus.get(c), threshold);
+            if distance <= threshold {
+                matches.push((distance, c));
+            }
+        };
+        match &index {
+            Some(index) => index.for_each_candidate(probe, &mut check),
+            None => (0..corpus.len()).for_each(&mut check),
+        }
+
+        matches.sort_unstable();
+        if top_k > 0 {
+            matches.truncate(top_k);
+        }
+        for &(distance, c) in &matches {
+            triples.extend_from_slice(&[q as i32, c as i32, distance]);
+        }
+    }
+
+    triples
+}
+
+/// Compares a batch of incoming hash strings against an existing library without comparing
+/// either set with itself. Returns `[query, corpus, distance]` triples (free with
+/// `free_pairs`); `top_k > 0` keeps only the closest `top_k` matches per query.
+#[no_mangle]
+pub extern "C" fn find_cross_matches(
+    queries: *const *const u8,
+    num_queries: i32,
+    corpus: *const *const u8,
+    num_corpus: i32,
+    threshold: i32,
+    top_k: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if queries.is_null() || corpus.is_null() || num_queries <= 0 || num_corpus <= 0 {
+        return null_mut();
+    }
+
+    let parsed_queries = parse_hash_array(queries, num_queries as usize);
+    let parsed_corpus = parse_hash_array(corpus, num_corpus as usize);
+
+    let mut matches: Vec<[i32; 3]> = Vec::new();
+    for group in compatible_groups(&parsed_corpus) {
+        let query_ids: Vec<usize> = (0..parsed_queries.len())
+            .filter(|&q| hash_compatibility(&parsed_corpus[group[0]], &parsed_queries[q]).is_ok())
+            .collect();
+        if query_ids.is_empty() {
+            continue;
+        }
+
+        let (corpus_data, words) = group_blocks(&parsed_corpus, &group);
+        let (query_data, _) = group_blocks(&parsed_queries, &query_ids);
+        let triples = collect_cross_matches(
+            HashBlocks {
+                data: &query_data,
+                words,
+            },
+            HashBlocks {
+                data: &corpus_data,
+                words,
+            },
+            threshold,
+            top_k.max(0) as usize,
+        );
+        matches.extend(triples.chunks_exact(3).map(|triple| {
+            [
+                query_ids[triple[0] as usize] as i32,
+                group[triple[1] as usize] as i32,
+                triple[2],
+            ]
+        }));
+    }
+
+    // A query is compatible with at most one group, so a stable sort by query keeps each
+    // query's matches in ranked order.
+    matches.sort_by_key(|triple| triple[0]);
+    let triples: Vec<i32> = matches.into_iter().flatten().collect();
+    matches_output(&triples, true, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_cross_matches_packed(
+    queries: *const u64,
+    num_queries: i32,
+    corpus: *const u64,
+    num_corpus: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    top_k: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    let (Some(queries), Some(corpus)) = (
+        HashBlocks::from_raw(queries, num_queries, words_per_hash),
+        HashBlocks::from_raw(corpus, num_corpus, words_per_hash),
+    ) else {
+        return null_mut();
+    };
+
+    let triples = collect_cross_matches(queries, corpus, threshold, top_k.max(0) as usize);
+    matches_output(&triples, true, out_count)
+}
+
+// ===== Nearest Neighbors =====
+
+/// The `k` smallest distances among `num` candidates, as flattened `[index, distance]`
+/// pairs sorted by distance then index. Once `k` candidates are held, the current worst
+/// distance is passed as the early-stop bound so hopeless candidates are abandoned after
+/// a few blocks. `distance` returns a negative code for incomparable candidates.
+fn collect_nearest_neighbors(
+    num: usize,
+    k: usize,
+    distance: impl Fn(usize, i32) -> i32,
+) -> Vec<i32> {
+    let mut best: BinaryHeap<(i32, usize)> = BinaryHeap::with_capacity(k.min(num) + 1);
+
+    for i in 0..num {
+        let bound = if best.len() == k {
+            best.peek().map_or(-1, |&(worst, _)| worst)
+        } else {
+            -1
+        };
+
+        let d = distance(i, bound);
+        if d < 0 || (bound >= 0 && d >= bound) {
+            // Incomparable, pruned, or tied with the worst kept (which has a lower index).
+            continue;
+        }
+
+        best.push((d, i));
+        if best.len() > k {
+            best.pop();
+        }
+    }
+
+    best.into_sorted_vec()
+        .into_iter()
+        .flat_map(|(d, i)| [i as i32, d])
+        .collect()
+}
+
+/// Indices of the `k` hash strings closest to `probe`, with distances, as flattened
+/// `[index, distance]` pairs sorted ascending (free with `free_pairs`). Hashes that cannot
+/// be compared with the probe are skipped.
+#[no_mangle]
+pub extern "C" fn find_nearest_neighbors(
+    probe: *const u8,
+    hashes: *const *const u8,
+    num_hashes: i32,
+    k: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    if hashes.is_null() || num_hashes <= 0 || k <= 0 {
+        return null_mut();
+    }
+
+    let probe = parse_packed_hash(probe);
+    if !probe.valid {
+        return null_mut();
+    }
+
+    let parsed_hashes = parse_hash_array(hashes, num_hashes as usize);
+    let neighbors = collect_nearest_neighbors(parsed_hashes.len(), k as usize, |i, early_stop| {
+        hamming_distance_packed(&probe, &parsed_hashes[i], early_stop)
+    });
+    pairs_output(&neighbors, out_count)
+}
+
+#[no_mangle]
+pub extern "C" fn find_nearest_neighbors_packed(
+    probe: *const u64,
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    k: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    let (Some(probe), Some(hashes)) = (
+        HashBlocks::from_raw(probe, 1, words_per_hash),
+        HashBlocks::from_raw(blocks, num_hashes, words_per_hash),
+    ) else {
+        return null_mut();
+    };
+    if k <= 0 {
+        return null_mut();
+    }
+
+    let neighbors = collect_nearest_neighbors(hashes.len(), k as usize, |i, early_stop| {
+        hamming_distance_words(probe.get(0), hashes.get(i), early_stop)
+    });
+    pairs_output(&neighbors, out_count)
+}
+
+// ===== Duplicate Clustering =====
+
+const REPRESENTATIVE_NONE: i32 = 0;
+/// Lowest input index in the group.
+const REPRESENTATIVE_FIRST: i32 = 1;
+/// Member with the smallest total distance to the rest of its group.
+const REPRESENTATIVE_MEDOID: i32 = 2;
+
+/// Connected components of the "within threshold" graph.
+/// `group_ids` has one entry per input hash: its group, or -1 when it has no similar
+/// partner (or could not be parsed). Groups are numbered by their lowest member index.
+/// `representatives` is null unless a representative mode was requested.
+#[repr(C)]
+pub struct ClusterResult {
+    pub group_ids: *mut i32,
+    pub group_sizes: *mut i32,
+    pub representatives: *mut i32,
+    pub num_groups: i32,
+    pub error: i32,
+    pub error_message: *mut u8,
+}
+
+fn create_cluster_result(value: ClusterResult) -> *mut ClusterResult {
+    let ptr = alloc_bytes(size_of::<ClusterResult>()) as *mut ClusterResult;
+    if ptr.is_null() {
+        free_cluster_buffers(&value);
+        return null_mut();
+    }
+
+    // SAFETY: ptr points to writable memory for ClusterResult.
+    unsafe {
+        ptr.write(value);
+    }
+
+    ptr
+}
+
+fn create_cluster_error(message: &str) -> *mut ClusterResult {
+    create_cluster_result(ClusterResult {
+        group_ids: null_mut(),
+        group_sizes: null_mut(),
+        representatives: null_mut(),
+        num_groups: 0,
+        error: 1,
+        error_message: alloc_c_string(message),
+    })
+}
+
+fn free_cluster_buffers(value: &ClusterResult) {
+    dealloc_bytes(value.group_ids as *mut u8);
+    dealloc_bytes(value.group_sizes as *mut u8);
+    dealloc_bytes(value.representatives as *mut u8);
+    dealloc_bytes(value.error_message);
+}
+
+/// Union-find with union by rank and path halving.
+struct DisjointSet {
+    parent: Vec<u32>,
+    rank: Vec<u8>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        DisjointSet {
+            parent: (0..len as u32).collect(),
+            rank: vec![0; len],
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] as usize != x {
+            let grandparent = self.parent[self.parent[x] as usize];
+            self.parent[x] = grandparent;
+            x = grandparent as usize;
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a == root_b {
+            return;
+        }
+
+        match self.rank[root_a].cmp(&self.rank[root_b]) {
+            core::cmp::Ordering::Less => self.parent[root_a] = root_b as u32,
+            core::cmp::Ordering::Greater => self.parent[root_b] = root_a as u32,
+            core::cmp::Ordering::Equal => {
+                self.parent[root_b] = root_a as u32;
+                self.rank[root_a] += 1;
+            }
+        }
+    }
+}
+
+/// Groups `num` items from flattened `[i, j, distance]` matches and builds the FFI result.
+/// `distance` is only consulted for `REPRESENTATIVE_MEDOID`.
+fn cluster_from_pairs(
+    num: usize,
+    pairs: &[i32],
+    representative_mode: i32,
+    distance: impl Fn(usize, usize) -> i32,
+) -> *mut ClusterResult {
+    let mut sets = DisjointSet::new(num);
+    for pair in pairs.chunks_exact(3) {
+        sets.union(pair[0] as usize, pair[1] as usize);
+    }
+
+    let mut root_sizes = vec![0u32; num];
+    for i in 0..num {
+        let root = sets.find(i);
+        root_sizes[root] += 1;
+    }
+
+    let mut root_groups = vec![-1i32; num];
+    let mut group_ids = vec![-1i32; num];
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for (i, group_id) in group_ids.iter_mut().enumerate() {
+        let root = sets.find(i);
+        if root_sizes[root] < 2 {
+            continue;
+        }
+        if root_groups[root] < 0 {
+            root_groups[root] = members.len() as i32;
+            members.push(Vec::new());
+        }
+        *group_id = root_groups[root];
+        members[*group_id as usize].push(i);
+    }
+
+    let group_sizes: Vec<i32> = members.iter().map(|group| group.len() as i32).collect();
+    let representatives: Vec<i32> = match representative_mode {
+        REPRESENTATIVE_FIRST => members.iter().map(|group| group[0] as i32).collect(),
+        REPRESENTATIVE_MEDOID => members
+            .iter()
+            .map(|group| {
+                let total = |&a: &usize| -> i64 {
+                    group.iter().map(|&b| distance(a, b).max(0) as i64).sum()
+                };
+                // min_by_key keeps the first (lowest index) member on ties.
+                *group.iter().min_by_key(|a| total(a)).unwrap_or(&group[0]) as i32
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let value = ClusterResult {
+        group_ids: alloc_array(&group_ids),
+        group_sizes: alloc_array(&group_sizes),
+        representatives: alloc_array(&representatives),
+        num_groups: members.len() as i32,
+        error: 0,
+        error_message: null_mut(),
+    };
+
+    let allocation_failed = (value.group_ids.is_null() && num > 0)
+        || (value.group_sizes.is_null() && !group_sizes.is_empty())
+        || (value.representatives.is_null() && !representatives.is_empty());
+    if allocation_failed {
+        free_cluster_buffers(&value);
+        return create_cluster_error("Failed to allocate cluster result");
+    }
+
+    create_cluster_result(value)
+}
+
+fn check_representative_mode(representative_mode: i32) -> bool {
+    matches!(
+        representative_mode,
+        REPRESENTATIVE_NONE | REPRESENTATIVE_FIRST | REPRESENTATIVE_MEDOID
+    )
+}
+
+/// Clusters hash strings whose pairwise distance is within `threshold` (transitively),
+/// replacing a pair list plus a JS-side union-find. Release with `free_cluster_result`.
+#[no_mangle]
+pub extern "C" fn cluster_similar_hashes(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    threshold: i32,
+    representative_mode: i32,
+) -> *mut ClusterResult {
+    if hashes.is_null() || num_hashes <= 0 || !check_representative_mode(representative_mode) {
+        return create_cluster_error("Invalid input parameters");
+    }
+
+    let parsed_hashes = parse_hash_array(hashes, num_hashes as usize);
+    let pairs = collect_similar_pairs_mih_parsed(&parsed_hashes, |_| threshold);
+    cluster_from_pairs(parsed_hashes.len(), &pairs, representative_mode, |i, j| {
+        hamming_distance_packed(&parsed_hashes[i], &parsed_hashes[j], -1)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn cluster_similar_hashes_packed(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+    representative_mode: i32,
+) -> *mut ClusterResult {
+    if !check_representative_mode(representative_mode) {
+        return create_cluster_error("Invalid input parameters");
+    }
+    let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+        return create_cluster_error("Invalid input parameters");
+    };
+
+    let pairs = collect_similar_pairs_mih(hashes, threshold);
+    cluster_from_pairs(hashes.len(), &pairs, representative_mode, |i, j| {
+        hamming_distance_words(hashes.get(i), hashes.get(j), -1)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_cluster_result(result: *mut ClusterResult) {
+    if result.is_null() {
+        return;
+    }
+
+    // SAFETY: result is a valid pointer returned by create_cluster_result.
+    let value = unsafe { result.read() };
+    free_cluster_buffers(&value);
+    dealloc_bytes(result as *mut u8);
+}
+
+// ===== BK-Tree Index =====
+
+/// In-memory BK-tree over packed hashes for radius queries under Hamming distance.
+/// Item `i` is node `i`; removal only tombstones the node so it keeps routing queries
+/// and indices stay stable for the lifetime of the handle.
+pub struct BkTree {
+    words: usize,
+    hashes: Vec<u64>,
+    removed: Vec<bool>,
+    /// `(edge distance, child node)` pairs per node.
+    children: Vec<Vec<(u32, u32)>>,
+    live: usize,
+}
+
+impl BkTree {
+    fn new(words: usize) -> Self {
+        BkTree {
+            words,
+            hashes: Vec::new(),
+            removed: Vec::new(),
+            children: Vec::new(),
+            live: 0,
+        }
+    }
+
+    fn hash(&self, index: usize) -> &[u64] {
+        &self.hashes[index * self.words..(index + 1) * self.words]
+    }
+
+    fn insert(&mut self, hash: &[u64]) -> usize {
+        let index = self.removed.len();
+        self.hashes.extend_from_slice(hash);
+        self.removed.push(false);
+        self.children.push(Vec::new());
+        self.live += 1;
+
+        if index == 0 {
+            return index;
+        }
+
+        let mut node = 0usize;
+        loop {
+            let distance = hamming_distance_words(self.hash(node), hash, -1) as u32;
+            match self.children[node]
+                .iter()
+                .find(|&&(edge, _)| edge == distance)
+            {
+                Some(&(_, child)) => node = child as usize,
+                None => {
+                    self.children[node].push((distance, index as u32));
+                    return index;
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> bool {
+        match self.removed.get_mut(index) {
+            Some(removed) if !*removed => {
+                *removed = true;
+                self.live -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Live items within `radius` of `probe`, in ascending index order.
+    fn query(&self, probe: &[u64], radius: u32) -> Vec<i32> {
+        let mut found = Vec::new();
+        if self.removed.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0usize];
+        while let Some(node) = stack.pop() {
+            let distance = hamming_distance_words(self.hash(node), probe, -1) as u32;
+            if distance <= radius && !self.removed[node] {
+                found.push(node as i32);
+            }
+
+            let low = distance.saturating_sub(radius);
+            let high = distance.saturating_add(radius);
+            for &(edge, child) in &self.children[node] {
+                if edge >= low && edge <= high {
+                    stack.push(child as usize);
+                }
+            }
+        }
+
+        found.sort_unstable();
+        found
+    }
+}
+
+/// Builds a BK-tree from `num_hashes` packed hashes (`num_hashes` may be 0 for an empty
+/// tree). Item indices follow input order. Release with `bktree_free`.
+#[no_mangle]
+pub extern "C" fn bktree_create(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+) -> *mut BkTree {
+    if words_per_hash <= 0 || num_hashes < 0 {
+        return null_mut();
+    }
+
+    let mut tree = BkTree::new(words_per_hash as usize);
+    if num_hashes > 0 {
+        let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+            return null_mut();
+        };
+        for i in 0..hashes.len() {
+            tree.insert(hashes.get(i));
+        }
+    }
+
+    Box::into_raw(Box::new(tree))
+}
+
+/// Adds one packed hash and returns its item index, or -1 on invalid input.
+#[no_mangle]
+pub extern "C" fn bktree_insert(tree: *mut BkTree, hash: *const u64) -> i32 {
+    // SAFETY: tree is null or a live handle from bktree_create.
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return -1;
+    };
+    let Some(hash) = HashBlocks::from_raw(hash, 1, tree.words as i32) else {
+        return -1;
+    };
+
+    tree.insert(hash.get(0)) as i32
+}
+
+/// Removes item `index`. Returns 1 if it was present, 0 otherwise.
+#[no_mangle]
+pub extern "C" fn bktree_remove(tree: *mut BkTree, index: i32) -> i32 {
+    // SAFETY: tree is null or a live handle from bktree_create.
+    let Some(tree) = (unsafe { tree.as_mut() }) else {
+        return 0;
+    };
+    if index < 0 {
+        return 0;
+    }
+
+    tree.remove(index as usize) as i32
+}
+
+/// Number of live (not removed) items.
+#[no_mangle]
+pub extern "C" fn bktree_len(tree: *const BkTree) -> i32 {
+    // SAFETY: tree is null or a live handle from bktree_create.
+    unsafe { tree.as_ref() }.map_or(0, |tree| tree.live as i32)
+}
+
+/// Indices of all live items within Hamming `radius` of `probe`, ascending.
+/// Release with `free_pairs`.
+#[no_mangle]
+pub extern "C" fn bktree_query(
+    tree: *const BkTree,
+    probe: *const u64,
+    radius: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    // SAFETY: tree is null or a live handle from bktree_create.
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return null_mut();
+    };
+    let Some(probe) = HashBlocks::from_raw(probe, 1, tree.words as i32) else {
+        return null_mut();
+    };
+    if radius < 0 {
+        return null_mut();
+    }
+
+    let found = tree.query(probe.get(0), radius as u32);
+    write_out_i32(out_count, found.len() as i32);
+    alloc_array(&found)
+}
+
+#[no_mangle]
+pub extern "C" fn bktree_free(tree: *mut BkTree) {
+    if tree.is_null() {
+        return;
+    }
+
+    // SAFETY: tree was created by Box::into_raw in bktree_create or bktree_deserialize.
+    drop(unsafe { Box::from_raw(tree) });
+}
+
+/// Serialized BK-tree layout (all integers little-endian):
+/// magic `PHBK`, format version (u32), words per hash (u32), bits per hash (u32), the
+/// length (u32) and bytes of the hashes' tag (see `HashTag`), node count (u32), then per
+/// node its hash words (u64 each), a removed flag (u8), and its parent node and edge
+/// distance (u32 each; `u32::MAX` for the root). Children are rebuilt in node order,
+/// which reproduces the original tree exactly. Loading checks the tag against the one the
+/// caller expects, so an index built under another algorithm, preprocessing or hash size
+/// is never queried, and checks every edge against the actual parent distance, since a
+/// wrong edge would silently hide items from queries.
+const BKTREE_MAGIC: [u8; 4] = *b"PHBK";
+const BKTREE_FORMAT_VERSION: u32 = 2;
+const BKTREE_NO_PARENT: u32 = u32::MAX;
+/// Longest accepted tag; real tags are a few dozen bytes.
+const BKTREE_MAX_TAG_BYTES: usize = 256;
+
+impl BkTree {
+    fn node_bytes(words: usize) -> Option<usize> {
+        words.checked_mul(8)?.checked_add(1 + 8)
+    }
+
+    /// Bytes before the first node record for a tag of `tag_len` bytes.
+    fn header_bytes(tag_len: usize) -> usize {
+        24 + tag_len
+    }
+
+    /// Whether `tag` describes hashes stored in this tree's `words` blocks.
+    fn fits_tag(words: usize, tag: &HashTag) -> bool {
+        tag.bits.div_ceil(64) == words
+    }
+
+    fn serialize(&self, tag: &HashTag) -> Vec<u8> {
+        let num = self.removed.len();
+        let mut parents = vec![(BKTREE_NO_PARENT, BKTREE_NO_PARENT); num];
+        for (node, children) in self.children.iter().enumerate() {
+            for &(edge, child) in children {
+                parents[child as usize] = (node as u32, edge);
+            }
+        }
+
+        let node_bytes = Self::node_bytes(self.words).unwrap_or(0);
+        let mut out = Vec::with_capacity(Self::header_bytes(tag.text.len()) + num * node_bytes);
+        out.extend_from_slice(&BKTREE_MAGIC);
+        out.extend_from_slice(&BKTREE_FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.words as u32).to_le_bytes());
+        out.extend_from_slice(&(tag.bits as u32).to_le_bytes());
+        out.extend_from_slice(&(tag.text.len() as u32).to_le_bytes());
+        out.extend_from_slice(tag.text.as_bytes());
+        out.extend_from_slice(&(num as u32).to_le_bytes());
+        for (node, &(parent, edge)) in parents.iter().enumerate() {
+            for word in self.hash(node) {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+            out.push(self.removed[node] as u8);
+            out.extend_from_slice(&parent.to_le_bytes());
+            out.extend_from_slice(&edge.to_le_bytes());
+        }
+
+        out
+    }
+
+    /// Rebuilds a tree from `serialize` output, or `None` if the blob is malformed or was
+    /// saved for hashes other than `expected`.
+    fn deserialize(bytes: &[u8], expected: &HashTag) -> Option<Self> {
+        let read_u32 = |offset: usize| -> Option<u32> {
+            let chunk = bytes.get(offset..offset.checked_add(4)?)?;
+            Some(u32::from_le_bytes(chunk.try_into().ok()?))
+        };
+
+        if bytes.get(..4)? != BKTREE_MAGIC || read_u32(4)? != BKTREE_FORMAT_VERSION {
+            return None;
+        }
+        let words = read_u32(8)? as usize;
+        let bits = read_u32(12)? as usize;
+        let tag_len = read_u32(16)? as usize;
+        if tag_len > BKTREE_MAX_TAG_BYTES
+            || bits != expected.bits
+            || bytes.get(20..20 + tag_len)? != expected.text.as_bytes()
+            || !Self::fits_tag(words, expected)
+        {
+            return None;
+        }
+
+        let header_bytes = Self::header_bytes(tag_len);
+        let num = read_u32(header_bytes - 4)? as usize;
+        let node_bytes = Self::node_bytes(words)?;
+        if words == 0 || num.checked_mul(node_bytes)?.checked_add(header_bytes)? != bytes.len() {
+            return None;
+        }
+
+        let mut tree = BkTree::new(words);
+        tree.hashes.reserve_exact(num * words);
+        for node in 0..num {
+            let record = &bytes[header_bytes + node * node_bytes..][..node_bytes];
+            let (hash, rest) = record.split_at(words * 8);
+            tree.hashes.extend(
+                hash.chunks_exact(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().unwrap())),
+            );
+
+            let removed = match rest[0] {
+                0 => false,
+                1 => true,
+                _ => return None,
+            };
+            tree.removed.push(removed);
+            tree.children.push(Vec::new());
+            if !removed {
+                tree.live += 1;
+            }
+
+            let parent = u32::from_le_bytes(rest[1..5].try_into().unwrap());
+            let edge = u32::from_le_bytes(rest[5..9].try_into().unwrap());
+            match (node, parent) {
+                (0, BKTREE_NO_PARENT) => {}
+                (0, _) | (_, BKTREE_NO_PARENT) => return None,
+                _ if parent as usize >= node => return None,
+                _ => {
+                    let parent = parent as usize;
+                    let distance = hamming_distance_words(tree.hash(parent), tree.hash(node), -1);
+                    let siblings = &mut tree.children[parent];
+                    if distance as u32 != edge || siblings.iter().any(|&(e, _)| e == edge) {
+                        return None;
+                    }
+                    siblings.push((edge, node as u32));
+                }
+            }
+        }
+
+        Some(tree)
+    }
+}
+
+/// Tag a BK-tree export was given (a tagged hash of the set, or just its
+/// `v<version>:<algorithm field>:<bits>` prefix), if it matches the tree's block size.
+fn bktree_export_tag(hash_tag: *const u8, words: usize) -> Option<HashTag> {
+    let tag = parse_hash_tag(&parse_c_hex(hash_tag)?)?;
+    (BkTree::fits_tag(words, &tag) && tag.text.len() <= BKTREE_MAX_TAG_BYTES).then_some(tag)
+}
+
+/// Serializes a BK-tree (hashes, removal state and tree structure) into a versioned byte
+/// blob that `bktree_deserialize` loads without re-inserting anything. `hash_tag` names
+/// the indexed hashes (any tagged hash of the set, or its `v1:<algorithm>:<bits>`
+/// prefix) and is recorded so the blob only loads for the same kind of hash. Item indices
+/// are preserved, but the blob holds no caller ids: persist the index-to-id mapping
+/// alongside it. Returns null if the tag does not fit the tree's words per hash. Release
+/// with `free_index_bytes`.
+#[no_mangle]
+pub extern "C" fn bktree_serialize(
+    tree: *const BkTree,
+    hash_tag: *const u8,
+    out_len: *mut i32,
+) -> *mut u8 {
+    write_out_i32(out_len, 0);
+
+    // SAFETY: tree is null or a live handle from bktree_create.
+    let Some(tree) = (unsafe { tree.as_ref() }) else {
+        return null_mut();
+    };
+    let Some(tag) = bktree_export_tag(hash_tag, tree.words) else {
+        return null_mut();
+    };
+
+    let bytes = tree.serialize(&tag);
+    let Ok(len) = i32::try_from(bytes.len()) else {
+        return null_mut();
+    };
+    write_out_i32(out_len, len);
+    alloc_array(&bytes)
+}
+
+/// Loads a blob produced by `bktree_serialize`. `hash_tag` is the tag the caller's current
+/// hashes carry, in either form `bktree_serialize` accepts; a blob saved for another
+/// algorithm, preprocessing or bit length is refused so it gets rebuilt. Returns null for
+/// such mismatches, unknown versions or corrupted data. Release with `bktree_free`.
+#[no_mangle]
+pub extern "C" fn bktree_deserialize(
+    data: *const u8,
+    len: i32,
+    hash_tag: *const u8,
+) -> *mut BkTree {
+    if data.is_null() || len <= 0 {
+        return null_mut();
+    }
+    let Some(expected) = parse_c_hex(hash_tag).and_then(|tag| parse_hash_tag(&tag)) else {
+        return null_mut();
+    };
+
+    // SAFETY: caller guarantees data points to len bytes.
+    let bytes = unsafe { core::slice::from_raw_parts(data, len as usize) };
+    match BkTree::deserialize(bytes, &expected) {
+        Some(tree) => Box::into_raw(Box::new(tree)),
+        None => null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn free_index_bytes(bytes: *mut u8) {
+    dealloc_bytes(bytes);
+}
+
+// ===== Incremental Similarity Jobs =====
+
+const JOB_RUNNING: i32 = 0;
+const JOB_DONE: i32 = 1;
+const JOB_ABORTED: i32 = 2;
+
+/// Resumable all-pairs scan over an owned copy of packed hashes. Each step compares a
+/// bounded number of pairs in the same `(i, j)` order as `find_similar_pairs_packed`, so
+/// the accumulated matches of a finished job equal that export's output.
+pub struct SimilarityJob {
+    words: usize,
+    hashes: Vec<u64>,
+    threshold: i32,
+    next_i: usize,
+    next_j: usize,
+    compared: u64,
+    total: u64,
+    matches: Vec<i32>,
+    status: i32,
+}
+
+impl SimilarityJob {
+    fn len(&self) -> usize {
+        self.hashes.len() / self.words
+    }
+
+    fn hash(&self, index: usize) -> &[u64] {
+        &self.hashes[index * self.words..(index + 1) * self.words]
+    }
+
+    fn step(&mut self, max_comparisons: u64) {
+        let num = self.len();
+        let early_stop = self.threshold.max(0);
+        let mut budget = max_comparisons;
+
+        while self.status == JOB_RUNNING && self.compared < self.total && budget > 0 {
+            if self.next_j >= num {
+                self.next_i += 1;
+                self.next_j = self.next_i + 1;
+                continue;
+            }
+
+            let (i, j) = (self.next_i, self.next_j);
+            let d = hamming_distance_words(self.hash(i), self.hash(j), early_stop);
+            if d <= self.threshold {
+                self.matches.extend_from_slice(&[i as i32, j as i32, d]);
+            }
+
+            self.next_j += 1;
+            self.compared += 1;
+            budget -= 1;
+        }
+
+        if self.status == JOB_RUNNING && self.compared == self.total {
+            self.status = JOB_DONE;
+        }
+    }
+}
+
+/// Starts a thresholded pair search over `num_hashes` packed hashes (copied, so the
+/// caller may free `blocks` right away). No work happens until `similarity_job_step`.
+/// Release with `similarity_job_free`.
+#[no_mangle]
+pub extern "C" fn similarity_job_create(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    threshold: i32,
+) -> *mut SimilarityJob {
+    let Some(hashes) = HashBlocks::from_raw(blocks, num_hashes, words_per_hash) else {
+        return null_mut();
+    };
+
+    let num = hashes.len() as u64;
+    let total = num * num.saturating_sub(1) / 2;
+    let job = SimilarityJob {
+        words: hashes.words,
+        hashes: hashes.data.to_vec(),
+        threshold,
+        next_i: 0,
+        next_j: 1,
+        compared: 0,
+        total,
+        matches: Vec::new(),
+        status: if total == 0 || threshold < 0 {
+            JOB_DONE
+        } else {
+            JOB_RUNNING
+        },
+    };
+
+    Box::into_raw(Box::new(job))
+}
+
+/// Performs at most `max_comparisons` distance computations and returns the job status:
+/// 0 while running, 1 once finished, 2 after `similarity_job_abort`, -1 on invalid input.
+#[no_mangle]
+pub extern "C" fn similarity_job_step(job: *mut SimilarityJob, max_comparisons: i32) -> i32 {
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    let Some(job) = (unsafe { job.as_mut() }) else {
+        return -1;
+    };
+    if max_comparisons <= 0 {
+        return -1;
+    }
+
+    job.step(max_comparisons as u64);
+    job.status
+}
+
+#[no_mangle]
+pub extern "C" fn similarity_job_status(job: *const SimilarityJob) -> i32 {
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    unsafe { job.as_ref() }.map_or(-1, |job| job.status)
+}
+
+/// Fraction of comparisons performed, in `[0, 1]`. A finished job always reports 1.
+#[no_mangle]
+pub extern "C" fn similarity_job_progress(job: *const SimilarityJob) -> f64 {
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    let Some(job) = (unsafe { job.as_ref() }) else {
+        return 0.0;
+    };
+    if job.status == JOB_DONE {
+        return 1.0;
+    }
+
+    job.compared as f64 / job.total as f64
+}
+
+/// Number of matches found so far.
+#[no_mangle]
+pub extern "C" fn similarity_job_match_count(job: *const SimilarityJob) -> i32 {
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    unsafe { job.as_ref() }.map_or(0, |job| (job.matches.len() / 3) as i32)
+}
+
+/// Matches found so far starting at match `start`, as `[i, j]` pairs or, with
+/// `with_distances`, `[i, j, distance]` triples. Passing the previous match count as
+/// `start` fetches only new matches. Release with `free_pairs`.
+#[no_mangle]
+pub extern "C" fn similarity_job_results(
+    job: *const SimilarityJob,
+    start: i32,
+    with_distances: i32,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
+
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    let Some(job) = (unsafe { job.as_ref() }) else {
+        return null_mut();
+    };
+    if start < 0 {
+        return null_mut();
+    }
+
+    let offset = (start as usize).saturating_mul(3).min(job.matches.len());
+    matches_output(&job.matches[offset..], with_distances != 0, out_count)
+}
+
+/// Stops a running job; matches found so far stay readable until `similarity_job_free`.
+#[no_mangle]
+pub extern "C" fn similarity_job_abort(job: *mut SimilarityJob) {
+    // SAFETY: job is null or a live handle from similarity_job_create.
+    if let Some(job) = unsafe { job.as_mut() } {
+        if job.status == JOB_RUNNING {
+            job.status = JOB_ABORTED;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn similarity_job_free(job: *mut SimilarityJob) {
+    if job.is_null() {
+        return;
+    }
+
+    // SAFETY: job was created by Box::into_raw in similarity_job_create.
+    drop(unsafe { Box::from_raw(job) });
+}
+
+// ===== Content Digests =====
+
+/// Prefix of content digest strings; the payload is the 128-bit XXH3 value as 32 hex digits.
+const CONTENT_DIGEST_PREFIX: &str = "xxh3-128:";
+
+/// Pixels hashed per buffered update in `rgba_content_digest`.
+const DIGEST_CHUNK_PIXELS: usize = 4096;
+
+fn format_content_digest(digest: u128) -> String {
+    format!("{CONTENT_DIGEST_PREFIX}{digest:032x}")
+}
+
+/// Digest of decoded pixels that ignores encoding details: the dimensions are hashed first
+/// so reshaped buffers differ, and fully transparent pixels count as `0, 0, 0, 0` whatever
+/// colour the encoder left behind.
+fn rgba_content_digest(image_data: &[u8], width: usize, height: usize) -> u128 {
+    let mut hasher = Xxh3::new();
+    hasher.update(&(width as u32).to_le_bytes());
+    hasher.update(&(height as u32).to_le_bytes());
+
+    let mut buffer = Vec::with_capacity(DIGEST_CHUNK_PIXELS * 4);
+    for chunk in image_data.chunks(DIGEST_CHUNK_PIXELS * 4) {
+        buffer.clear();
+        for px in chunk.chunks_exact(4) {
+            if px[3] == 0 {
+                buffer.extend_from_slice(&[0; 4]);
+            } else {
+                buffer.extend_from_slice(px);
+            }
+        }
+        hasher.update(&buffer);
+    }
+
+    hasher.digest128()
+}
+
+fn digest_result(digest: u128) -> *mut HashResult {
+    let digest_ptr = alloc_c_string(&format_content_digest(digest));
+    if digest_ptr.is_null() {
+        return create_error_result("Failed to allocate hash result");
+    }
+    create_hash_result(digest_ptr, 0, null_mut())
+}
+
+/// Exact-duplicate key for a file: XXH3-128 over the raw encoded bytes, returned as
+/// `xxh3-128:<hex>`. Free with `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_content_digest(data: *const u8, len: i32) -> *mut HashResult {
+    if len < 0 || (data.is_null() && len > 0) {
+        return create_error_result("Invalid input parameters");
+    }
+
+    let bytes = if len == 0 {
+        &[][..]
+    } else {
+        // SAFETY: caller guarantees data points to len bytes.
+        unsafe { core::slice::from_raw_parts(data, len as usize) }
+    };
+    digest_result(xxh3_128(bytes))
+}
+
+/// Exact-duplicate key for decoded RGBA pixels, equal for the same image re-encoded
+/// losslessly or in another container. Free with `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_pixel_digest(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+) -> *mut HashResult {
+    if image_data.is_null() || width <= 0 || height <= 0 {
+        return create_error_result("Invalid input parameters");
+    }
+
+    let Some(image_len) = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(4))
+    else {
+        return create_error_result("Image size overflow");
+    };
+
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image_slice = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+    digest_result(rgba_content_digest(
+        image_slice,
+        width as usize,
+        height as usize,
+    ))
+}
+
+// ===== Color Quantization =====
+
+/// Result struct for color quantization operations.
+/// `colors_ptr` points to a flat array of `[r, g, b, population]` u32 tuples.
+#[repr(C)]
+pub struct ColorResult {
+    pub colors_ptr: *mut u32,
+    pub num_colors: i32,
+    pub error: i32,
+    pub error_message: *mut u8,
+}
+
+fn create_color_result(
+    colors_ptr: *mut u32,
+    num_colors: i32,
+    error: i32,
+    error_message: *mut u8,
+) -> *mut ColorResult {
+    let ptr = alloc_bytes(size_of::<ColorResult>()) as *mut ColorResult;
+    if ptr.is_null() {
+        if !colors_ptr.is_null() {
+            dealloc_bytes(colors_ptr as *mut u8);
+        }
+        if !error_message.is_null() {
+            dealloc_bytes(error_message);
+        }
+        return null_mut();
+    }
+
+    unsafe {
+        ptr.write(ColorResult {
+            colors_ptr,
+            num_colors,
+            error,
+            error_message,
+        });
+    }
+
+    ptr
+}
+
+fn create_color_error(message: &str) -> *mut ColorResult {
+    let error_message = alloc_c_string(message);
+    create_color_result(null_mut(), 0, 1, error_message)
+}
+
+/// Allocate and populate a flat u32 array with [r, g, b, population] tuples.
+fn alloc_color_array(colors: &[(u32, u32, u32, u32)]) -> *mut u32 {
+    if colors.is_empty() {
+        return null_mut();
+    }
+
+    let total_u32s = colors.len() * 4;
+    let Some(total_bytes) = total_u32s.checked_mul(size_of::<u32>()) else {
+        return null_mut();
+    };
+
+    let ptr = alloc_bytes(total_bytes) as *mut u32;
+    if ptr.is_null() {
+        return null_mut();
+    }
+
+    for (i, &(r, g, b, pop)) in colors.iter().enumerate() {
+        unsafe {
+            *ptr.add(i * 4) = r;
+            *ptr.add(i * 4 + 1) = g;
+            *ptr.add(i * 4 + 2) = b;
+            *ptr.add(i * 4 + 3) = pop;
+        }
+    }
+
+    ptr
+}
+
+#[inline]
+fn color_distance_sq(r1: u32, g1: u32, b1: u32, r2: u32, g2: u32, b2: u32) -> u32 {
+    let dr = r1.wrapping_sub(r2);
+    let dg = g1.wrapping_sub(g2);
+    let db = b1.wrapping_sub(b2);
+    // Use i32 to handle negative differences correctly
+    let dr = dr as i32;
+    let dg = dg as i32;
+    let db = db as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+/// Extract RGB pixels from RGBA data, skipping transparent pixels.
+fn extract_rgb_pixels(pixel_data: &[u8], skip_alpha: u8) -> Vec<(u32, u32, u32)> {
+    let num_pixels = pixel_data.len() / 4;
+    let mut pixels = Vec::with_capacity(num_pixels);
+
+    for i in 0..num_pixels {
+        let a = pixel_data[i * 4 + 3];
+        if a < skip_alpha {
+            continue;
+        }
+        let r = pixel_data[i * 4] as u32;
+        let g = pixel_data[i * 4 + 1] as u32;
+        let b = pixel_data[i * 4 + 2] as u32;
+        pixels.push((r, g, b));
+    }
+
+    pixels
+}
+
+/// K-Means clustering on RGBA pixel data.
+#[no_mangle]
+pub extern "C" fn kmeans_quantize(
+    pixel_data: *const u8,
+    width: i32,
+    height: i32,
+    k: i32,
+    max_iterations: i32,
+    skip_alpha_threshold: u8,
+) -> *mut ColorResult {
+    if pixel_data.is_null() || width <= 0 || height <= 0 || k <= 0 {
+        return create_color_error("Invalid input parameters");
+    }
+
+    let w = width as usize;
+    let h = height as usize;
+    let k = k as usize;
+    let max_iter = if max_iterations <= 0 { 20 } else { max_iterations as usize };
+
+    let Some(num_pixels) = w.checked_mul(h) else {
+        return create_color_error("Pixel count overflow");
+    };
+    let Some(data_len) = num_pixels.checked_mul(4) else {
+        return create_color_error("Data size overflow");
+    };
+
+    let data = unsafe { core::slice::from_raw_parts(pixel_data, data_len) };
+    let pixels = extract_rgb_pixels(data, skip_alpha_threshold);
+
+    if pixels.is_empty() {
+        return create_color_result(null_mut(), 0, 0, null_mut());
+    }
+
+    if pixels.len() <= k {
+        let colors: Vec<(u32, u32, u32, u32)> = pixels.iter().map(|&(r, g, b)| (r, g, b, 1)).collect();
+        let ptr = alloc_color_array(&colors);
+        return create_color_result(ptr, colors.len() as i32, 0, null_mut());
+    }
+
+    // Initialize centroids by evenly sampling the pixel array (deterministic)
+    let mut centroids: Vec<(u32, u32, u32)> = Vec::with_capacity(k);
+    for i in 0..k {
+        let idx = (i * pixels.len()) / k;
+        centroids.push(pixels[idx]);
+    }
+
+    // Cluster assignment buffer
+    let mut assignments = vec![0usize; pixels.len()];
+    let mut cluster_counts = vec![0u64; k];
+    let mut cluster_sums_r = vec![0u64; k];
+    let mut cluster_sums_g = vec![0u64; k];
+    let mut cluster_sums_b = vec![0u64; k];
+
+    for _iter in 0..max_iter {
+        // Assign each pixel to nearest centroid
+        for (pi, &(r, g, b)) in pixels.iter().enumerate() {
+            let mut min_dist = u32::MAX;
+            let mut min_idx = 0usize;
+            for (ci, &(cr, cg, cb)) in centroids.iter().enumerate() {
+                let dist = color_distance_sq(r, g, b, cr, cg, cb);
+                if dist < min_dist {
+                    min_dist = dist;
+                    min_idx = ci;
+                }
+            }
+            assignments[pi] = min_idx;
+        }
+
+        // Recompute centroids
+        for i in 0..k {
+            cluster_counts[i] = 0;
+            cluster_sums_r[i] = 0;
+            cluster_sums_g[i] = 0;
+            cluster_sums_b[i] = 0;
+        }
+
+        for (pi, &(r, g, b)) in pixels.iter().enumerate() {
+            let ci = assignments[pi];
+            cluster_counts[ci] += 1;
             cluster_sums_r[ci] += r as u64;
             cluster_sums_g[ci] += g as u64;
             cluster_sums_b[ci] += b as u64;
         }
 
-        let mut changed = false;
-        for i in 0..k {
-            if cluster_counts[i] == 0 {
-                continue;
-            }
-            let new_r = (cluster_sums_r[i] / cluster_counts[i]) as u32;
-            let new_g = (cluster_sums_g[i] / cluster_counts[i]) as u32;
-            let new_b = (cluster_sums_b[i] / cluster_counts[i]) as u32;
+        let mut changed = false;
+        for i in 0..k {
+            if cluster_counts[i] == 0 {
+                continue;
+            }
+            let new_r = (cluster_sums_r[i] / cluster_counts[i]) as u32;
+            let new_g = (cluster_sums_g[i] / cluster_counts[i]) as u32;
+            let new_b = (cluster_sums_b[i] / cluster_counts[i]) as u32;
+
+            if color_distance_sq(new_r, new_g, new_b, centroids[i].0, centroids[i].1, centroids[i].2) > 1 {
+                changed = true;
+                centroids[i] = (new_r, new_g, new_b);
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    // Build output with population counts
+    let mut colors: Vec<(u32, u32, u32, u32)> = Vec::with_capacity(k);
+    for i in 0..k {
+        if cluster_counts[i] > 0 {
+            colors.push((centroids[i].0, centroids[i].1, centroids[i].2, cluster_counts[i] as u32));
+        }
+    }
+
+    // Sort by population descending
+    colors.sort_by_key(|c| core::cmp::Reverse(c.3));
+
+    let ptr = alloc_color_array(&colors);
+    create_color_result(ptr, colors.len() as i32, 0, null_mut())
+}
+
+/// Median Cut algorithm on RGBA pixel data.
+#[no_mangle]
+pub extern "C" fn median_cut_quantize(
+    pixel_data: *const u8,
+    width: i32,
+    height: i32,
+    num_colors: i32,
+    skip_alpha_threshold: u8,
+) -> *mut ColorResult {
+    if pixel_data.is_null() || width <= 0 || height <= 0 || num_colors <= 0 {
+        return create_color_error("Invalid input parameters");
+    }
+
+    let w = width as usize;
+    let h = height as usize;
+
+    let Some(num_pixels) = w.checked_mul(h) else {
+        return create_color_error("Pixel count overflow");
+    };
+    let Some(data_len) = num_pixels.checked_mul(4) else {
+        return create_color_error("Data size overflow");
+    };
+
+    let data = unsafe { core::slice::from_raw_parts(pixel_data, data_len) };
+    let pixels = extract_rgb_pixels(data, skip_alpha_threshold);
+
+    if pixels.is_empty() {
+        return create_color_result(null_mut(), 0, 0, null_mut());
+    }
+
+    // Calculate depth from num_colors: depth = ceil(log2(num_colors))
+    let target = num_colors as usize;
+    let depth = if target <= 1 { 0 } else { (target as f64).log2().ceil() as usize };
+
+    let result_pixels = median_cut_impl(pixels, depth);
+
+    let mut colors: Vec<(u32, u32, u32, u32)> = result_pixels;
+
+    // Sort by population descending
+    colors.sort_by_key(|c| core::cmp::Reverse(c.3));
+
+    let ptr = alloc_color_array(&colors);
+    create_color_result(ptr, colors.len() as i32, 0, null_mut())
+}
+
+fn median_cut_impl(
+    mut pixels: Vec<(u32, u32, u32)>,
+    depth: usize,
+) -> Vec<(u32, u32, u32, u32)> {
+    if depth == 0 || pixels.is_empty() {
+        if pixels.is_empty() {
+            return Vec::new();
+        }
+        // Return average color with population
+        let count = pixels.len() as u64;
+        let (sr, sg, sb) = pixels.iter().fold((0u64, 0u64, 0u64), |(sr, sg, sb), &(r, g, b)| {
+            (sr + r as u64, sg + g as u64, sb + b as u64)
+        });
+        return vec![((sr / count) as u32, (sg / count) as u32, (sb / count) as u32, count as u32)];
+    }
+
+    // Find the channel with the largest range
+    let (mut min_r, mut max_r) = (255u32, 0u32);
+    let (mut min_g, mut max_g) = (255u32, 0u32);
+    let (mut min_b, mut max_b) = (255u32, 0u32);
+
+    for &(r, g, b) in &pixels {
+        if r < min_r { min_r = r; }
+        if r > max_r { max_r = r; }
+        if g < min_g { min_g = g; }
+        if g > max_g { max_g = g; }
+        if b < min_b { min_b = b; }
+        if b > max_b { max_b = b; }
+    }
+
+    let range_r = max_r - min_r;
+    let range_g = max_g - min_g;
+    let range_b = max_b - min_b;
+
+    // Sort by the channel with the largest range
+    if range_r >= range_g && range_r >= range_b {
+        pixels.sort_unstable_by_key(|&(r, _, _)| r);
+    } else if range_g >= range_r && range_g >= range_b {
+        pixels.sort_unstable_by_key(|&(_, g, _)| g);
+    } else {
+        pixels.sort_unstable_by_key(|&(_, _, b)| b);
+    }
+
+    let mid = pixels.len() / 2;
+    let right = pixels.split_off(mid);
+
+    let mut results = median_cut_impl(pixels, depth - 1);
+    results.extend(median_cut_impl(right, depth - 1));
+    results
+}
+
+#[no_mangle]
+pub extern "C" fn free_color_result(result: *mut ColorResult) {
+    if result.is_null() {
+        return;
+    }
+
+    unsafe {
+        let value = result.read();
+        if !value.colors_ptr.is_null() {
+            dealloc_bytes(value.colors_ptr as *mut u8);
+        }
+        if !value.error_message.is_null() {
+            dealloc_bytes(value.error_message);
+        }
+    }
+
+    dealloc_bytes(result as *mut u8);
+}
+
+// ===== Image Decoding =====
+
+/// Largest accepted decoded width or height.
+const MAX_DECODE_DIMENSION: u32 = 16384;
+/// Upper bound on decoder allocations, including the RGBA output.
+const MAX_DECODE_ALLOC_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Encoded formats accepted by the decoding exports. The discriminants are the FFI ids
+/// reported by `detect_image_format` and `ImageResult::format`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum EncodedFormat {
+    Png = 1,
+    Jpeg = 2,
+    Gif = 3,
+    WebP = 4,
+}
+
+impl EncodedFormat {
+    /// Identifies the format from its magic bytes.
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(EncodedFormat::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(EncodedFormat::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(EncodedFormat::Gif)
+        } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            Some(EncodedFormat::WebP)
+        } else {
+            None
+        }
+    }
+
+    fn image_format(self) -> image::ImageFormat {
+        match self {
+            EncodedFormat::Png => image::ImageFormat::Png,
+            EncodedFormat::Jpeg => image::ImageFormat::Jpeg,
+            EncodedFormat::Gif => image::ImageFormat::Gif,
+            EncodedFormat::WebP => image::ImageFormat::WebP,
+        }
+    }
+}
+
+struct DecodedImage {
+    rgba: Vec<u8>,
+    width: usize,
+    height: usize,
+    format: EncodedFormat,
+}
+
+fn decode_limits() -> image::Limits {
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC_BYTES);
+    limits
+}
+
+/// Decodes the first frame of a PNG, JPEG, GIF or WebP file to straight-alpha RGBA8.
+fn decode_rgba(bytes: &[u8]) -> Result<DecodedImage, &'static str> {
+    let format = EncodedFormat::sniff(bytes).ok_or("Unsupported image format")?;
+
+    let mut reader =
+        image::ImageReader::with_format(std::io::Cursor::new(bytes), format.image_format());
+    reader.limits(decode_limits());
+    let decoded = reader.decode().map_err(|err| match err {
+        image::ImageError::Limits(_) => "Image exceeds decoding limits",
+        _ => "Failed to decode image",
+    })?;
+
+    let rgba = decoded.into_rgba8();
+    let (width, height) = rgba.dimensions();
+    Ok(DecodedImage {
+        rgba: rgba.into_raw(),
+        width: width as usize,
+        height: height as usize,
+        format,
+    })
+}
+
+/// Borrows the caller's encoded buffer, or `None` for a null pointer or empty length.
+fn encoded_bytes<'a>(data: *const u8, len: i32) -> Option<&'a [u8]> {
+    if data.is_null() || len <= 0 {
+        return None;
+    }
+
+    // SAFETY: caller guarantees data points to len bytes.
+    Some(unsafe { core::slice::from_raw_parts(data, len as usize) })
+}
+
+/// Result struct for RGBA image outputs. `pixels` holds `width * height * 4` RGBA bytes and
+/// `format` is the sniffed format id when decoding (1 PNG, 2 JPEG, 3 GIF, 4 WebP), else 0.
+#[repr(C)]
+pub struct ImageResult {
+    pub pixels: *mut u8,
+    pub width: i32,
+    pub height: i32,
+    pub format: i32,
+    pub error: i32,
+    pub error_message: *mut u8,
+}
+
+fn create_image_result(value: ImageResult) -> *mut ImageResult {
+    let ptr = alloc_bytes(size_of::<ImageResult>()) as *mut ImageResult;
+    if ptr.is_null() {
+        dealloc_bytes(value.pixels);
+        dealloc_bytes(value.error_message);
+        return null_mut();
+    }
+
+    // SAFETY: ptr is a fresh allocation sized for ImageResult.
+    unsafe {
+        ptr.write(value);
+    }
+
+    ptr
+}
+
+fn create_image_error(message: &str) -> *mut ImageResult {
+    create_image_result(ImageResult {
+        pixels: null_mut(),
+        width: 0,
+        height: 0,
+        format: 0,
+        error: 1,
+        error_message: alloc_c_string(message),
+    })
+}
+
+/// Format id of an encoded image from its magic bytes, or 0 if unsupported.
+#[no_mangle]
+pub extern "C" fn detect_image_format(data: *const u8, len: i32) -> i32 {
+    encoded_bytes(data, len)
+        .and_then(EncodedFormat::sniff)
+        .map_or(0, |format| format as i32)
+}
+
+/// Decodes encoded image bytes (first frame for animations) to RGBA without a canvas.
+/// Free with `free_image_result`.
+#[no_mangle]
+pub extern "C" fn decode_image(data: *const u8, len: i32) -> *mut ImageResult {
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_image_error("Invalid input parameters");
+    };
+
+    match decode_rgba(bytes) {
+        Ok(image) => {
+            let pixels = alloc_array(&image.rgba);
+            if pixels.is_null() {
+                return create_image_error("Failed to allocate image result");
+            }
+            create_image_result(ImageResult {
+                pixels,
+                width: image.width as i32,
+                height: image.height as i32,
+                format: image.format as i32,
+                error: 0,
+                error_message: null_mut(),
+            })
+        }
+        Err(message) => create_image_error(message),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn free_image_result(result: *mut ImageResult) {
+    if result.is_null() {
+        return;
+    }
+
+    // SAFETY: result was allocated by create_image_result.
+    let value = unsafe { result.read() };
+    dealloc_bytes(value.pixels);
+    dealloc_bytes(value.error_message);
+    dealloc_bytes(result as *mut u8);
+}
+
+/// Decodes encoded image bytes and hashes them with `algorithm` (a `HashAlgorithm` id)
+/// using its default parameters. Free with `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_hash_from_encoded(
+    data: *const u8,
+    len: i32,
+    algorithm: i32,
+    hash_size: i32,
+) -> *mut HashResult {
+    calculate_hash_from_encoded_with_options(data, len, algorithm, hash_size, core::ptr::null())
+}
+
+/// `calculate_hash_with_options` over encoded image bytes.
+#[no_mangle]
+pub extern "C" fn calculate_hash_from_encoded_with_options(
+    data: *const u8,
+    len: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_error_result("Invalid input parameters");
+    };
+
+    let image = match decode_rgba(bytes) {
+        Ok(image) => image,
+        Err(message) => return create_error_result(message),
+    };
+    calculate_hash_with_options(
+        image.rgba.as_ptr(),
+        image.width as i32,
+        image.height as i32,
+        algorithm,
+        hash_size,
+        options,
+    )
+}
+
+/// `kmeans_quantize_trimmed` over encoded image bytes.
+#[no_mangle]
+pub extern "C" fn kmeans_quantize_encoded(
+    data: *const u8,
+    len: i32,
+    k: i32,
+    max_iterations: i32,
+    skip_alpha_threshold: u8,
+    trim_alpha_threshold: i32,
+    trim_tolerance: i32,
+) -> *mut ColorResult {
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_color_error("Invalid input parameters");
+    };
+
+    match decode_rgba(bytes) {
+        Ok(image) => kmeans_quantize_trimmed(
+            image.rgba.as_ptr(),
+            image.width as i32,
+            image.height as i32,
+            k,
+            max_iterations,
+            skip_alpha_threshold,
+            trim_alpha_threshold,
+            trim_tolerance,
+        ),
+        Err(message) => create_color_error(message),
+    }
+}
+
+/// `median_cut_quantize_trimmed` over encoded image bytes.
+#[no_mangle]
+pub extern "C" fn median_cut_quantize_encoded(
+    data: *const u8,
+    len: i32,
+    num_colors: i32,
+    skip_alpha_threshold: u8,
+    trim_alpha_threshold: i32,
+    trim_tolerance: i32,
+) -> *mut ColorResult {
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_color_error("Invalid input parameters");
+    };
+
+    match decode_rgba(bytes) {
+        Ok(image) => median_cut_quantize_trimmed(
+            image.rgba.as_ptr(),
+            image.width as i32,
+            image.height as i32,
+            num_colors,
+            skip_alpha_threshold,
+            trim_alpha_threshold,
+            trim_tolerance,
+        ),
+        Err(message) => create_color_error(message),
+    }
+}
+
+// ===== Animated Images =====
+
+/// Most frames decoded from one animation; later frames are ignored.
+const MAX_ANIMATION_FRAMES: usize = 4096;
+/// Browsers show GIF frames with a delay of 10 ms or less for this long instead.
+const CLAMPED_FRAME_DELAY_MS: u32 = 100;
+
+/// Frame delay in milliseconds. The short-delay clamp is a GIF playback quirk, so WebP
+/// delays are taken as stored.
+fn frame_delay_ms(delay: image::Delay, format: EncodedFormat) -> u32 {
+    let (numer, denom) = delay.numer_denom_ms();
+    let ms = numer.checked_div(denom).unwrap_or(0);
+    if format == EncodedFormat::Gif && ms <= 10 {
+        CLAMPED_FRAME_DELAY_MS
+    } else {
+        ms
+    }
+}
+
+/// Calls `visit(rgba, width, height, delay_ms)` for each fully composited frame of an
+/// animated GIF or WebP (disposal and blending applied by the decoder); still images
+/// yield one frame. Frames are streamed so only one canvas is alive at a time.
+fn for_each_frame(
+    bytes: &[u8],
+    mut visit: impl FnMut(&[u8], usize, usize, u32) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    use image::{AnimationDecoder, ImageDecoder};
+
+    let format = EncodedFormat::sniff(bytes).ok_or("Unsupported image format")?;
+    let decode_error = |err: image::ImageError| match err {
+        image::ImageError::Limits(_) => "Image exceeds decoding limits",
+        _ => "Failed to decode image",
+    };
+
+    let frames = match format {
+        EncodedFormat::Gif => {
+            let mut decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(bytes))
+                .map_err(decode_error)?;
+            decoder.set_limits(decode_limits()).map_err(decode_error)?;
+            decoder.into_frames()
+        }
+        EncodedFormat::WebP => {
+            let mut decoder = image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(bytes))
+                .map_err(decode_error)?;
+            if !decoder.has_animation() {
+                let image = decode_rgba(bytes)?;
+                return visit(&image.rgba, image.width, image.height, 0);
+            }
+            decoder.set_limits(decode_limits()).map_err(decode_error)?;
+            decoder.into_frames()
+        }
+        EncodedFormat::Png | EncodedFormat::Jpeg => {
+            let image = decode_rgba(bytes)?;
+            return visit(&image.rgba, image.width, image.height, 0);
+        }
+    };
+
+    for frame in frames.take(MAX_ANIMATION_FRAMES) {
+        let frame = frame.map_err(decode_error)?;
+        let delay = frame_delay_ms(frame.delay(), format);
+        let buffer = frame.buffer();
+        let (width, height) = buffer.dimensions();
+        visit(buffer.as_raw(), width as usize, height as usize, delay)?;
+    }
+
+    Ok(())
+}
+
+/// Per-bit majority over frame hashes weighted by display time, so the aggregate is an
+/// ordinary hash of the same algorithm and length that reflects what is on screen most.
+fn aggregate_frame_bits(frames: &[Vec<bool>], delays: &[u32]) -> Vec<bool> {
+    let bits = frames.first().map_or(0, Vec::len);
+    let weights: Vec<u64> = delays.iter().map(|&delay| delay.max(1) as u64).collect();
+    let total: u64 = weights.iter().sum();
+
+    (0..bits)
+        .map(|bit| {
+            let set: u64 = frames
+                .iter()
+                .zip(&weights)
+                .filter(|(frame, _)| frame[bit])
+                .map(|(_, &weight)| weight)
+                .sum();
+            set * 2 > total
+        })
+        .collect()
+}
+
+/// Result struct for animation hashing. `frames` holds `num_frames` packed hashes of
+/// `words_per_hash` u64s each (same layout as `pack_hashes`), `delays` the display time of
+/// each frame in ms (0 for still images), and `aggregate` a tagged hash string of the whole
+/// animation comparable with still-image hashes of the same algorithm.
+#[repr(C)]
+pub struct AnimationResult {
+    pub frames: *mut u64,
+    pub delays: *mut i32,
+    pub num_frames: i32,
+    pub words_per_hash: i32,
+    pub aggregate: *mut u8,
+    pub error: i32,
+    pub error_message: *mut u8,
+}
+
+fn create_animation_result(value: AnimationResult) -> *mut AnimationResult {
+    let ptr = alloc_bytes(size_of::<AnimationResult>()) as *mut AnimationResult;
+    if ptr.is_null() {
+        free_animation_buffers(&value);
+        return null_mut();
+    }
+
+    // SAFETY: ptr is a fresh allocation sized for AnimationResult.
+    unsafe {
+        ptr.write(value);
+    }
+
+    ptr
+}
+
+fn create_animation_error(message: &str) -> *mut AnimationResult {
+    create_animation_result(AnimationResult {
+        frames: null_mut(),
+        delays: null_mut(),
+        num_frames: 0,
+        words_per_hash: 0,
+        aggregate: null_mut(),
+        error: 1,
+        error_message: alloc_c_string(message),
+    })
+}
+
+fn free_animation_buffers(value: &AnimationResult) {
+    dealloc_bytes(value.frames as *mut u8);
+    dealloc_bytes(value.delays as *mut u8);
+    dealloc_bytes(value.aggregate);
+    dealloc_bytes(value.error_message);
+}
+
+/// Decodes every frame of encoded image bytes and hashes each with `algorithm` (a
+/// `HashAlgorithm` id), plus a duration-weighted aggregate. `options` is a preprocessing
+/// block as in `calculate_hash_with_options` (null for the defaults), applied per frame.
+/// Free with `free_animation_result`.
+#[no_mangle]
+pub extern "C" fn calculate_animation_hashes(
+    data: *const u8,
+    len: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut AnimationResult {
+    let Some(algorithm) = HashAlgorithm::from_id(algorithm) else {
+        return create_animation_error("Unknown hash algorithm");
+    };
+    let options = match read_preprocess_options(options) {
+        Ok(options) => options,
+        Err(message) => return create_animation_error(message),
+    };
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_animation_error("Invalid input parameters");
+    };
+    if hash_size <= 0 {
+        return create_animation_error("Invalid input parameters");
+    }
+
+    let mut frames: Vec<Vec<bool>> = Vec::new();
+    let mut delays: Vec<u32> = Vec::new();
+    let decoded = for_each_frame(bytes, |rgba, width, height, delay| {
+        frames.push(preprocessed_hash_bits(
+            rgba,
+            width,
+            height,
+            algorithm,
+            hash_size as usize,
+            &options,
+        )?);
+        delays.push(delay);
+        Ok(())
+    });
+    if let Err(message) = decoded {
+        return create_animation_error(message);
+    }
+    if frames.is_empty() {
+        return create_animation_error("Image has no frames");
+    }
+
+    let words = frames[0].len().div_ceil(64);
+    let mut blocks = vec![0u64; frames.len() * words];
+    for (frame, out) in frames.iter().zip(blocks.chunks_exact_mut(words)) {
+        bits_to_blocks(frame, out);
+    }
+    let delays_ms: Vec<i32> = delays.iter().map(|&delay| delay as i32).collect();
+    let aggregate = encode_hash_with(algorithm, &options, &aggregate_frame_bits(&frames, &delays));
+
+    let value = AnimationResult {
+        frames: alloc_array(&blocks),
+        delays: alloc_array(&delays_ms),
+        num_frames: frames.len() as i32,
+        words_per_hash: words as i32,
+        aggregate: alloc_c_string(&aggregate),
+        error: 0,
+        error_message: null_mut(),
+    };
+    if value.frames.is_null() || value.delays.is_null() || value.aggregate.is_null() {
+        free_animation_buffers(&value);
+        return create_animation_error("Failed to allocate animation result");
+    }
+    create_animation_result(value)
+}
+
+#[no_mangle]
+pub extern "C" fn free_animation_result(result: *mut AnimationResult) {
+    if result.is_null() {
+        return;
+    }
+
+    // SAFETY: result was allocated by create_animation_result.
+    free_animation_buffers(unsafe { &result.read() });
+    dealloc_bytes(result as *mut u8);
+}
+
+/// Dynamic time warping over two frame-hash sequences: the cheapest monotonic alignment
+/// where each frame matches one or more frames of the other sequence. Returns the mean
+/// Hamming distance along that alignment, so dropped, duplicated or retimed frames cost
+/// little while different content still scores high.
+fn frame_sequence_distance(a: HashBlocks, b: HashBlocks) -> f64 {
+    // (total cost, path length) of the best alignment ending at each cell of the row.
+    let mut previous: Vec<(u64, u32)> = vec![(u64::MAX, 0); b.len()];
+    let mut current = previous.clone();
+
+    for i in 0..a.len() {
+        for j in 0..b.len() {
+            let d = hamming_distance_words(a.get(i), b.get(j), -1) as u64;
+            let best = if i == 0 && j == 0 {
+                (0, 0)
+            } else {
+                let mut candidates = [(u64::MAX, 0); 3];
+                if i > 0 {
+                    candidates[0] = previous[j];
+                }
+                if j > 0 {
+                    candidates[1] = current[j - 1];
+                }
+                if i > 0 && j > 0 {
+                    candidates[2] = previous[j - 1];
+                }
+                candidates
+                    .into_iter()
+                    .min_by_key(|&(cost, len)| (cost, len))
+                    .unwrap()
+            };
+            current[j] = (best.0 + d, best.1 + 1);
+        }
+        core::mem::swap(&mut previous, &mut current);
+    }
+
+    let (cost, len) = previous[b.len() - 1];
+    cost as f64 / len as f64
+}
+
+/// Compares two animations from their `AnimationResult::frames` buffers (both with
+/// `words_per_hash` u64s per frame). Returns the mean per-frame Hamming distance of the best
+/// time alignment, or -1.0 on invalid input.
+#[no_mangle]
+pub extern "C" fn compare_animation_hashes(
+    frames_a: *const u64,
+    num_frames_a: i32,
+    frames_b: *const u64,
+    num_frames_b: i32,
+    words_per_hash: i32,
+) -> f64 {
+    let (Some(a), Some(b)) = (
+        HashBlocks::from_raw(frames_a, num_frames_a, words_per_hash),
+        HashBlocks::from_raw(frames_b, num_frames_b, words_per_hash),
+    ) else {
+        return -1.0;
+    };
+
+    frame_sequence_distance(a, b)
+}
+
+// ===== Orientation-Tolerant Matching =====
+
+/// Number of dihedral transforms: four rotations, each with and without mirroring.
+const DIHEDRAL_TRANSFORMS: usize = 8;
+
+/// Applies dihedral transform `transform` to an RGBA image: indices 0-3 rotate clockwise
+/// by 0°, 90°, 180° and 270°; 4-7 mirror horizontally first, then rotate the same way.
+/// Returns the pixels with the (possibly swapped) dimensions.
+fn transform_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    transform: usize,
+) -> (Vec<u8>, usize, usize) {
+    let quarter_turns = transform % 4;
+    let mirrored = transform >= 4;
+    let (out_width, out_height) = if quarter_turns % 2 == 1 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut out = Vec::with_capacity(out_width * out_height * 4);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (src_x, src_y) = match quarter_turns {
+                0 => (x, y),
+                1 => (y, height - 1 - x),
+                2 => (width - 1 - x, height - 1 - y),
+                _ => (width - 1 - y, x),
+            };
+            let src_x = if mirrored { width - 1 - src_x } else { src_x };
+            let offset = (src_y * width + src_x) * 4;
+            out.extend_from_slice(&image_data[offset..offset + 4]);
+        }
+    }
+
+    (out, out_width, out_height)
+}
+
+/// Hashes all 8 rotations and mirror images of an image with `algorithm` (a
+/// `HashAlgorithm` id). Entry `t` is the hash after transform `t` (see `transform_rgba`);
+/// entry 0 equals the plain hash. `options` is a preprocessing block as in
+/// `calculate_hash_with_options` (null for the defaults); borders are trimmed once before
+/// transforming. Invalid input sets `error` and the message on all 8 entries, like the
+/// other `HashResult` exports. Free with `free_batch_results(results, 8)`.
+#[no_mangle]
+pub extern "C" fn calculate_dihedral_hashes(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let results_ptr = alloc_bytes(DIHEDRAL_TRANSFORMS * size_of::<HashResult>()) as *mut HashResult;
+    if results_ptr.is_null() {
+        return null_mut();
+    }
+
+    let hashes = dihedral_hashes_export(image_data, width, height, algorithm, hash_size, options);
+    for transform in 0..DIHEDRAL_TRANSFORMS {
+        let hashed = match &hashes {
+            Ok(hashes) => Ok(hashes[transform].clone()),
+            Err(error) => Err(*error),
+        };
+
+        // SAFETY: results_ptr points to an array of DIHEDRAL_TRANSFORMS entries.
+        unsafe {
+            results_ptr.add(transform).write(hash_result_entry(hashed));
+        }
+    }
+
+    results_ptr
+}
+
+/// Validates the `calculate_dihedral_hashes` arguments and hashes every transform.
+fn dihedral_hashes_export(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    algorithm: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> Result<Vec<String>, &'static str> {
+    let algorithm = HashAlgorithm::from_id(algorithm).ok_or("Unknown hash algorithm")?;
+    let options = read_preprocess_options(options)?;
+    if image_data.is_null() || width <= 0 || height <= 0 || hash_size <= 0 {
+        return Err("Invalid input parameters");
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let image_len = width
+        .checked_mul(height)
+        .and_then(|v| v.checked_mul(4))
+        .ok_or("Image size overflow")?;
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+
+    let (image, width, height) = trim_rgba(image, width, height, &options.trim);
+    (0..DIHEDRAL_TRANSFORMS)
+        .map(|transform| {
+            let (pixels, w, h) = transform_rgba(&image, width, height, transform);
+            let bits = hash_bits_with_options(
+                &pixels,
+                w,
+                h,
+                algorithm,
+                hash_size as usize,
+                &options.gray,
+            )?;
+            Ok(encode_hash_with(algorithm, &options, &bits))
+        })
+        .collect()
+}
+
+/// Smallest distance between `hash` and any of `num_variants` transform hashes of one image
+/// (as returned by `calculate_dihedral_hashes`), writing the index of the matching variant
+/// to `out_transform`. Returns a negative `HASH_ERROR_*` code if no variant is comparable.
+#[no_mangle]
+pub extern "C" fn calculate_min_transform_distance(
+    variants: *const *const u8,
+    num_variants: i32,
+    hash: *const u8,
+    out_transform: *mut i32,
+) -> i32 {
+    write_out_i32(out_transform, -1);
+
+    if variants.is_null() || num_variants <= 0 {
+        return HASH_ERROR_INVALID;
+    }
+
+    let target = parse_packed_hash(hash);
+    let mut best: Option<(i32, usize)> = None;
+    let mut error = HASH_ERROR_INVALID;
+    for (transform, variant) in parse_hash_array(variants, num_variants as usize)
+        .iter()
+        .enumerate()
+    {
+        let early_stop = best.map_or(-1, |(distance, _)| distance);
+        let distance = hamming_distance_packed(variant, &target, early_stop);
+        if distance < 0 {
+            error = distance;
+        } else if best.is_none_or(|(best_distance, _)| distance < best_distance) {
+            best = Some((distance, transform));
+        }
+    }
+
+    match best {
+        Some((distance, transform)) => {
+            write_out_i32(out_transform, transform as i32);
+            distance
+        }
+        None => error,
+    }
+}
+
+// ===== Crop-Resistant Segmented Hashing =====
+
+/// Side of the square grid the image is segmented on.
+const SEGMENT_GRID_SIZE: usize = 128;
+/// Largest brightness step between neighbouring grid cells of one region.
+const SEGMENT_TOLERANCE: f32 = 16.0;
+/// Smallest kept segment, in grid cells (about 1% of the grid).
+const MIN_SEGMENT_CELLS: usize = SEGMENT_GRID_SIZE * SEGMENT_GRID_SIZE / 100;
+/// Segments hashed when the caller passes `max_segments <= 0`.
+const DEFAULT_MAX_SEGMENTS: usize = 16;
+/// Separator between segment hashes in a segmented hash string.
+const SEGMENT_SEPARATOR: u8 = b',';
+
+/// Inclusive grid bounding box and cell count of one connected region.
+struct Segment {
+    cells: usize,
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+/// Splits the image into 4-connected regions of gradually varying brightness on a
+/// `SEGMENT_GRID_SIZE` grid, largest first. Neighbouring cells join a region when their
+/// brightness differs by at most `SEGMENT_TOLERANCE`; unlike a global bright/dark
+/// threshold this does not shift when borders or captions are added. Regions smaller than
+/// `MIN_SEGMENT_CELLS` are dropped as noise.
+fn segment_regions(gray: &GrayImage) -> Vec<Segment> {
+    let size = SEGMENT_GRID_SIZE;
+    let grid = resize_area(gray, size, size);
+
+    let mut visited = vec![false; size * size];
+    let mut segments = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..size * size {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push(start);
+        let (x, y) = (start % size, start / size);
+        let mut segment = Segment {
+            cells: 0,
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        };
+        while let Some(cell) = stack.pop() {
+            let (x, y) = (cell % size, cell / size);
+            segment.cells += 1;
+            segment.min_x = segment.min_x.min(x);
+            segment.min_y = segment.min_y.min(y);
+            segment.max_x = segment.max_x.max(x);
+            segment.max_y = segment.max_y.max(y);
+
+            let neighbors = [
+                (x > 0).then(|| cell - 1),
+                (x + 1 < size).then(|| cell + 1),
+                (y > 0).then(|| cell - size),
+                (y + 1 < size).then(|| cell + size),
+            ];
+            for next in neighbors.into_iter().flatten() {
+                if !visited[next]
+                    && (grid.pixels[next] - grid.pixels[cell]).abs() <= SEGMENT_TOLERANCE
+                {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        if segment.cells >= MIN_SEGMENT_CELLS {
+            segments.push(segment);
+        }
+    }
+
+    // Stable sort keeps scan order for equal sizes.
+    segments.sort_by_key(|segment| core::cmp::Reverse(segment.cells));
+    segments
+}
+
+/// Maps an inclusive grid span back to a non-empty pixel range of an axis of length `len`.
+fn grid_span_to_pixels(min: usize, max: usize, len: usize) -> core::ops::Range<usize> {
+    let start = (min * len / SEGMENT_GRID_SIZE).min(len - 1);
+    let end = ((max + 1) * len)
+        .div_ceil(SEGMENT_GRID_SIZE)
+        .clamp(start + 1, len);
+    start..end
+}
+
+fn crop_rgba(
+    image_data: &[u8],
+    width: usize,
+    columns: core::ops::Range<usize>,
+    rows: core::ops::Range<usize>,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(columns.len() * rows.len() * 4);
+    for y in rows {
+        out.extend_from_slice(
+            &image_data[(y * width + columns.start) * 4..(y * width + columns.end) * 4],
+        );
+    }
+    out
+}
+
+/// Hashes the bounding box of each of the `max_segments` largest regions with `algorithm`
+/// and joins the tagged hashes with commas. A uniform image yields one whole-image segment.
+fn calculate_segmented_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    algorithm: HashAlgorithm,
+    hash_size: usize,
+    max_segments: usize,
+    options: &PreprocessOptions,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let image_data = rgba_pixels(image_data, width, height)?;
+    let (image_data, width, height) = trim_rgba(image_data, width, height, &options.trim);
+    let gray = rgba_to_gray_with(&image_data, width, height, &options.gray)?;
+    let hash_region = |data: &[u8], width: usize, height: usize| {
+        hash_bits_with_options(data, width, height, algorithm, hash_size, &options.gray)
+            .map(|bits| encode_hash_with(algorithm, options, &bits))
+    };
+
+    let mut hashes = Vec::new();
+    for segment in segment_regions(&gray).iter().take(max_segments) {
+        let columns = grid_span_to_pixels(segment.min_x, segment.max_x, width);
+        let rows = grid_span_to_pixels(segment.min_y, segment.max_y, height);
+        let (crop_width, crop_height) = (columns.len(), rows.len());
+        let crop = crop_rgba(&image_data, width, columns, rows);
+        hashes.push(hash_region(&crop, crop_width, crop_height)?);
+    }
+    if hashes.is_empty() {
+        hashes.push(hash_region(&image_data, width, height)?);
+    }
+
+    Ok(hashes.join(&(SEGMENT_SEPARATOR as char).to_string()))
+}
+
+/// Crop-resistant hash: the image is flood-filled into regions of gradually varying
+/// brightness (see `segment_regions`) and each region's bounding box is hashed with
+/// `algorithm` (a `HashAlgorithm` id), so padding, watermarks or caption strips only
+/// disturb some segments. Returns the segment hashes joined by `SEGMENT_SEPARATOR`, largest
+/// region first; `max_segments <= 0` keeps up to 16. `options` is a preprocessing block as
+/// in `calculate_hash_with_options` (null for the defaults); its luma and alpha settings
+/// also drive the segmentation. Compare with `match_segmented_hashes`. Free with
+/// `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_segmented_hash(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    algorithm: i32,
+    hash_size: i32,
+    max_segments: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let Some(algorithm) = HashAlgorithm::from_id(algorithm) else {
+        return create_error_result("Unknown hash algorithm");
+    };
+    let options = match read_preprocess_options(options) {
+        Ok(options) => options,
+        Err(message) => return create_error_result(message),
+    };
+    let max_segments = if max_segments > 0 {
+        max_segments as usize
+    } else {
+        DEFAULT_MAX_SEGMENTS
+    };
+
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        |data, width, height, hash_size| {
+            calculate_segmented_hash_for_rgba(
+                data,
+                width,
+                height,
+                algorithm,
+                hash_size,
+                max_segments,
+                &options,
+            )
+        },
+    )
+}
+
+/// Parses a comma-separated segmented hash; `None` if it is empty or any segment is invalid.
+fn parse_segmented_hash(ptr: *const u8) -> Option<Vec<PackedHash>> {
+    let bytes = parse_c_hex(ptr)?;
+    let segments: Vec<PackedHash> = bytes
+        .split(|&b| b == SEGMENT_SEPARATOR)
+        .map(parse_hash_bytes)
+        .collect();
+    segments
+        .iter()
+        .all(|segment| segment.valid)
+        .then_some(segments)
+}
+
+/// Counts segments of `hash1` that have a segment of `hash2` within `threshold` bits and
+/// writes the count to `out_matched`. Returns 1 when at least `min_segments` (at least 1)
+/// segments align, 0 when fewer do, or a negative `HASH_ERROR_*` code for invalid or
+/// incompatible input.
+#[no_mangle]
+pub extern "C" fn match_segmented_hashes(
+    hash1: *const u8,
+    hash2: *const u8,
+    threshold: i32,
+    min_segments: i32,
+    out_matched: *mut i32,
+) -> i32 {
+    write_out_i32(out_matched, 0);
+
+    let (Some(segments1), Some(segments2)) =
+        (parse_segmented_hash(hash1), parse_segmented_hash(hash2))
+    else {
+        return HASH_ERROR_INVALID;
+    };
+    if let Err(code) = hash_compatibility(&segments1[0], &segments2[0]) {
+        return code;
+    }
+
+    let early_stop = threshold.max(0);
+    let matched = segments1
+        .iter()
+        .filter(|segment1| {
+            segments2.iter().any(|segment2| {
+                let distance = hamming_distance_packed(segment1, segment2, early_stop);
+                distance >= 0 && distance <= threshold
+            })
+        })
+        .count();
+
+    write_out_i32(out_matched, matched as i32);
+    (matched >= min_segments.max(1) as usize) as i32
+}
+
+// ===== Border Trimming =====
+
+/// What counts as padding around the content. Negative fields disable that check.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct TrimOptions {
+    /// Pixels with alpha at or below this value are padding.
+    alpha_threshold: i32,
+    /// Opaque pixels within this per-channel distance of the background colour are padding.
+    /// The background colour is that of the four corners, used only when they all agree
+    /// within the same tolerance.
+    tolerance: i32,
+}
+
+/// Inclusive-exclusive pixel bounds `[x0, x1) x [y0, y1)` of the content.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+fn channel_distance(a: &[u8], b: &[u8]) -> i32 {
+    (0..3)
+        .map(|c| (a[c] as i32 - b[c] as i32).abs())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Bounding box of the non-padding pixels, or `None` when every pixel is padding.
+fn content_bounds(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    options: &TrimOptions,
+) -> Option<Bounds> {
+    let pixel = |x: usize, y: usize| &image_data[(y * width + x) * 4..][..4];
+
+    let corners = [
+        pixel(0, 0),
+        pixel(width - 1, 0),
+        pixel(0, height - 1),
+        pixel(width - 1, height - 1),
+    ];
+    let background = (options.tolerance >= 0
+        && corners.iter().all(|corner| {
+            corner[3] == 255 && channel_distance(corner, corners[0]) <= options.tolerance
+        }))
+    .then_some(corners[0]);
+
+    let is_padding = |px: &[u8]| {
+        (options.alpha_threshold >= 0 && px[3] as i32 <= options.alpha_threshold)
+            || background
+                .is_some_and(|bg| px[3] == 255 && channel_distance(px, bg) <= options.tolerance)
+    };
+
+    let mut bounds: Option<Bounds> = None;
+    for y in 0..height {
+        for x in 0..width {
+            if is_padding(pixel(x, y)) {
+                continue;
+            }
+            let b = bounds.get_or_insert(Bounds {
+                x0: x,
+                y0: y,
+                x1: x + 1,
+                y1: y + 1,
+            });
+            b.x0 = b.x0.min(x);
+            b.x1 = b.x1.max(x + 1);
+            b.y1 = y + 1;
+        }
+    }
+
+    bounds
+}
+
+/// Crops `image_data` to its content bounds; images that are all padding, or have none,
+/// are returned as they are.
+fn trim_rgba<'a>(
+    image_data: &'a [u8],
+    width: usize,
+    height: usize,
+    options: &TrimOptions,
+) -> (Cow<'a, [u8]>, usize, usize) {
+    match content_bounds(image_data, width, height, options) {
+        Some(b) if (b.x0, b.y0, b.x1, b.y1) != (0, 0, width, height) => (
+            Cow::Owned(crop_rgba(image_data, width, b.x0..b.x1, b.y0..b.y1)),
+            b.x1 - b.x0,
+            b.y1 - b.y0,
+        ),
+        _ => (Cow::Borrowed(image_data), width, height),
+    }
+}
+
+fn trim_options_valid(options: &TrimOptions) -> bool {
+    options.alpha_threshold <= 255 && options.tolerance <= 255
+}
+
+impl TrimOptions {
+    /// Whether either check is on, i.e. trimming can change the image.
+    fn enabled(&self) -> bool {
+        self.alpha_threshold >= 0 || self.tolerance >= 0
+    }
+}
+
+/// Writes the content bounding box `[x, y, width, height]` into `out_bounds` (4 i32s).
+/// Padding is transparent pixels (alpha <= `alpha_threshold`) and, when the four corners
+/// share one opaque colour within `tolerance`, pixels of that colour; pass -1 to disable
+/// either check. Returns 1 when content was found, 0 when the image is all padding (the
+/// full frame is reported), or -1 on invalid input.
+#[no_mangle]
+pub extern "C" fn detect_content_bounds(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    alpha_threshold: i32,
+    tolerance: i32,
+    out_bounds: *mut i32,
+) -> i32 {
+    let options = TrimOptions {
+        alpha_threshold,
+        tolerance,
+    };
+    if image_data.is_null() || out_bounds.is_null() || width <= 0 || height <= 0 {
+        return -1;
+    }
+    if !trim_options_valid(&options) {
+        return -1;
+    }
+    let Some(image_len) = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(4))
+    else {
+        return -1;
+    };
+
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+    let found = content_bounds(image, width as usize, height as usize, &options);
+    let b = found.unwrap_or(Bounds {
+        x0: 0,
+        y0: 0,
+        x1: width as usize,
+        y1: height as usize,
+    });
+
+    // SAFETY: caller guarantees out_bounds points to 4 writable i32s.
+    unsafe {
+        *out_bounds = b.x0 as i32;
+        *out_bounds.add(1) = b.y0 as i32;
+        *out_bounds.add(2) = (b.x1 - b.x0) as i32;
+        *out_bounds.add(3) = (b.y1 - b.y0) as i32;
+    }
+
+    found.is_some() as i32
+}
+
+/// Reads an export's RGBA buffer and trims its borders as in `trim_image`.
+fn trimmed_rgba_export<'a>(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    alpha_threshold: i32,
+    tolerance: i32,
+) -> Result<(Cow<'a, [u8]>, usize, usize), &'static str> {
+    let options = TrimOptions {
+        alpha_threshold,
+        tolerance,
+    };
+    if image_data.is_null() || width <= 0 || height <= 0 || !trim_options_valid(&options) {
+        return Err("Invalid input parameters");
+    }
+    let image_len = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(4))
+        .ok_or("Image size overflow")?;
+
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+    Ok(trim_rgba(image, width as usize, height as usize, &options))
+}
+
+/// Crops an RGBA image to the bounds `detect_content_bounds` reports. Free with
+/// `free_image_result`; `format` is 0.
+#[no_mangle]
+pub extern "C" fn trim_image(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    alpha_threshold: i32,
+    tolerance: i32,
+) -> *mut ImageResult {
+    let (pixels, out_width, out_height) =
+        match trimmed_rgba_export(image_data, width, height, alpha_threshold, tolerance) {
+            Ok(trimmed) => trimmed,
+            Err(message) => return create_image_error(message),
+        };
+
+    let pixels = alloc_array(&pixels);
+    if pixels.is_null() {
+        return create_image_error("Failed to allocate image result");
+    }
+    create_image_result(ImageResult {
+        pixels,
+        width: out_width as i32,
+        height: out_height as i32,
+        format: 0,
+        error: 0,
+        error_message: null_mut(),
+    })
+}
+
+/// `kmeans_quantize` after trimming the borders as in `trim_image`, so padding does not
+/// become a palette entry. -1 disables either trim check.
+#[no_mangle]
+pub extern "C" fn kmeans_quantize_trimmed(
+    pixel_data: *const u8,
+    width: i32,
+    height: i32,
+    k: i32,
+    max_iterations: i32,
+    skip_alpha_threshold: u8,
+    trim_alpha_threshold: i32,
+    trim_tolerance: i32,
+) -> *mut ColorResult {
+    match trimmed_rgba_export(
+        pixel_data,
+        width,
+        height,
+        trim_alpha_threshold,
+        trim_tolerance,
+    ) {
+        Ok((pixels, width, height)) => kmeans_quantize(
+            pixels.as_ptr(),
+            width as i32,
+            height as i32,
+            k,
+            max_iterations,
+            skip_alpha_threshold,
+        ),
+        Err(message) => create_color_error(message),
+    }
+}
+
+/// `median_cut_quantize` after trimming the borders as in `trim_image`. -1 disables either
+/// trim check.
+#[no_mangle]
+pub extern "C" fn median_cut_quantize_trimmed(
+    pixel_data: *const u8,
+    width: i32,
+    height: i32,
+    num_colors: i32,
+    skip_alpha_threshold: u8,
+    trim_alpha_threshold: i32,
+    trim_tolerance: i32,
+) -> *mut ColorResult {
+    match trimmed_rgba_export(
+        pixel_data,
+        width,
+        height,
+        trim_alpha_threshold,
+        trim_tolerance,
+    ) {
+        Ok((pixels, width, height)) => median_cut_quantize(
+            pixels.as_ptr(),
+            width as i32,
+            height as i32,
+            num_colors,
+            skip_alpha_threshold,
+        ),
+        Err(message) => create_color_error(message),
+    }
+}
+
+// ===== Resampling =====
+
+/// Largest accepted output width or height for `resize_image`.
+const MAX_RESIZE_DIMENSION: usize = 16384;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResampleFilter {
+    /// Coverage-weighted box average, as used internally for hashing.
+    Area = 0,
+    /// Triangle filter, widened when downscaling so every source pixel contributes.
+    Bilinear = 1,
+    /// Windowed sinc with three lobes, widened when downscaling.
+    Lanczos3 = 2,
+}
+
+impl ResampleFilter {
+    fn from_id(id: i32) -> Option<Self> {
+        [
+            ResampleFilter::Area,
+            ResampleFilter::Bilinear,
+            ResampleFilter::Lanczos3,
+        ]
+        .into_iter()
+        .find(|filter| *filter as i32 == id)
+    }
+
+    /// Normalized source weights per output sample.
+    fn spans(self, src_len: usize, dst_len: usize) -> Vec<ResampleSpan> {
+        match self {
+            ResampleFilter::Area => area_spans(src_len, dst_len),
+            ResampleFilter::Bilinear => ResampleKernel::Triangle.spans(src_len, dst_len),
+            ResampleFilter::Lanczos3 => ResampleKernel::Lanczos3.spans(src_len, dst_len),
+        }
+    }
+}
+
+/// Convolution kernels behind the non-area filters.
+#[derive(Clone, Copy)]
+enum ResampleKernel {
+    Triangle,
+    Lanczos3,
+}
+
+impl ResampleKernel {
+    fn support(self) -> f64 {
+        match self {
+            ResampleKernel::Triangle => 1.0,
+            ResampleKernel::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        let sinc = |x: f64| {
+            if x == 0.0 {
+                1.0
+            } else {
+                let px = core::f64::consts::PI * x;
+                px.sin() / px
+            }
+        };
+
+        match self {
+            ResampleKernel::Triangle => (1.0 - x.abs()).max(0.0),
+            ResampleKernel::Lanczos3 if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResampleKernel::Lanczos3 => 0.0,
+        }
+    }
+
+    /// Normalized source weights per output sample. Sample centres sit at `i + 0.5`, and
+    /// the kernel is stretched by the scale factor when downscaling.
+    fn spans(self, src_len: usize, dst_len: usize) -> Vec<ResampleSpan> {
+        let scale = src_len as f64 / dst_len as f64;
+        let filter_scale = scale.max(1.0);
+        let support = self.support() * filter_scale;
+
+        (0..dst_len)
+            .map(|i| {
+                let center = (i as f64 + 0.5) * scale;
+                let start = ((center - support).floor().max(0.0) as usize).min(src_len - 1);
+                let end = ((center + support).ceil() as usize).clamp(start + 1, src_len);
+
+                let raw: Vec<f64> = (start..end)
+                    .map(|s| self.weight((s as f64 + 0.5 - center) / filter_scale))
+                    .collect();
+                let total: f64 = raw.iter().sum();
+                let weights = if total.abs() > f64::EPSILON {
+                    raw.iter().map(|&w| (w / total) as f32).collect()
+                } else {
+                    // Kernel fell between samples; take the nearest one.
+                    let nearest = (center.floor() as usize).clamp(start, end - 1);
+                    (start..end).map(|s| (s == nearest) as u8 as f32).collect()
+                };
+
+                ResampleSpan { start, weights }
+            })
+            .collect()
+    }
+}
+
+/// Resizes straight-alpha RGBA. Filtering runs on premultiplied colour so transparent
+/// pixels do not bleed their hidden colour into visible edges.
+fn resize_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    dst_width: usize,
+    dst_height: usize,
+    filter: ResampleFilter,
+) -> Vec<u8> {
+    let premultiplied: Vec<[f32; 4]> = image_data
+        .chunks_exact(4)
+        .map(|px| {
+            let alpha = px[3] as f32 / 255.0;
+            [
+                px[0] as f32 * alpha,
+                px[1] as f32 * alpha,
+                px[2] as f32 * alpha,
+                px[3] as f32,
+            ]
+        })
+        .collect();
+
+    let x_spans = filter.spans(width, dst_width);
+    let y_spans = filter.spans(height, dst_height);
+    let accumulate = |acc: &mut [f32; 4], px: &[f32; 4], w: f32| {
+        for c in 0..4 {
+            acc[c] += px[c] * w;
+        }
+    };
+
+    // Horizontal pass: width x height -> dst_width x height.
+    let mut horizontal = Vec::with_capacity(dst_width * height);
+    for row in premultiplied.chunks_exact(width) {
+        for span in &x_spans {
+            let mut acc = [0f32; 4];
+            for (k, &w) in span.weights.iter().enumerate() {
+                accumulate(&mut acc, &row[span.start + k], w);
+            }
+            horizontal.push(acc);
+        }
+    }
+
+    // Vertical pass: dst_width x height -> dst_width x dst_height, then un-premultiply.
+    let mut out = Vec::with_capacity(dst_width * dst_height * 4);
+    for span in &y_spans {
+        for x in 0..dst_width {
+            let mut acc = [0f32; 4];
+            for (k, &w) in span.weights.iter().enumerate() {
+                accumulate(&mut acc, &horizontal[(span.start + k) * dst_width + x], w);
+            }
+
+            let alpha = acc[3].clamp(0.0, 255.0);
+            let unpremultiply = if alpha > 0.0 { 255.0 / alpha } else { 0.0 };
+            for &channel in &acc[..3] {
+                out.push((channel * unpremultiply).round().clamp(0.0, 255.0) as u8);
+            }
+            out.push(alpha.round() as u8);
+        }
+    }
+
+    out
+}
+
+/// Deterministic RGBA resize to `dst_width x dst_height` with `filter` 0 (area), 1
+/// (bilinear) or 2 (Lanczos3), giving identical output in every browser. Free with
+/// `free_image_result`; `format` is 0.
+#[no_mangle]
+pub extern "C" fn resize_image(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    dst_width: i32,
+    dst_height: i32,
+    filter: i32,
+) -> *mut ImageResult {
+    let Some(filter) = ResampleFilter::from_id(filter) else {
+        return create_image_error("Unknown resample filter");
+    };
+    if image_data.is_null() || width <= 0 || height <= 0 || dst_width <= 0 || dst_height <= 0 {
+        return create_image_error("Invalid input parameters");
+    }
+    let (dst_width, dst_height) = (dst_width as usize, dst_height as usize);
+    if dst_width > MAX_RESIZE_DIMENSION || dst_height > MAX_RESIZE_DIMENSION {
+        return create_image_error("Target size exceeds limits");
+    }
+    let Some(image_len) = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(4))
+    else {
+        return create_image_error("Image size overflow");
+    };
+
+    // SAFETY: caller provides a valid RGBA buffer of width * height * 4 bytes.
+    let image = unsafe { core::slice::from_raw_parts(image_data, image_len) };
+    let resized = resize_rgba(
+        image,
+        width as usize,
+        height as usize,
+        dst_width,
+        dst_height,
+        filter,
+    );
+
+    let pixels = alloc_array(&resized);
+    if pixels.is_null() {
+        return create_image_error("Failed to allocate image result");
+    }
+    create_image_result(ImageResult {
+        pixels,
+        width: dst_width as i32,
+        height: dst_height as i32,
+        format: 0,
+        error: 0,
+        error_message: null_mut(),
+    })
+}
+
+// ===== Composite Fingerprints =====
+
+/// Algorithms in a composite fingerprint, in record order.
+const COMPOSITE_ALGORITHMS: [HashAlgorithm; 3] = [
+    HashAlgorithm::Average,
+    HashAlgorithm::Difference,
+    HashAlgorithm::Dct,
+];
+/// Separator between the component hashes of a composite fingerprint.
+const COMPOSITE_SEPARATOR: u8 = b'|';
+
+/// aHash, dHash and pHash of one image from a single preprocessing pass, as
+/// `<ahash>|<dhash>|<phash>` tagged hashes.
+fn calculate_composite_hash_for_rgba(
+    image_data: &[u8],
+    width: usize,
+    height: usize,
+    hash_size: usize,
+    options: &PreprocessOptions,
+) -> Result<String, &'static str> {
+    check_hash_size(hash_size)?;
+    let image_data = rgba_pixels(image_data, width, height)?;
+    let (image_data, width, height) = trim_rgba(image_data, width, height, &options.trim);
+    let planes = hash_planes(&image_data, width, height, &options.gray)?;
+    let parts: Vec<String> = COMPOSITE_ALGORITHMS
+        .iter()
+        .map(|&algorithm| {
+            let bits = planes_hash_bits(&planes, algorithm, hash_size);
+            encode_hash_with(algorithm, options, &bits)
+        })
+        .collect();
+    Ok(parts.join(&(COMPOSITE_SEPARATOR as char).to_string()))
+}
+
+/// Computes aHash, dHash and pHash together and returns them as one composite record
+/// (`<ahash>|<dhash>|<phash>`). `options` is a preprocessing block as in
+/// `calculate_hash_with_options` (null for the defaults). Compare records with
+/// `compare_composite_hashes`. Free with `free_hash_result`.
+#[no_mangle]
+pub extern "C" fn calculate_composite_hash(
+    image_data: *const u8,
+    width: i32,
+    height: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let options = match read_preprocess_options(options) {
+        Ok(options) => options,
+        Err(message) => return create_error_result(message),
+    };
 
-            if color_distance_sq(new_r, new_g, new_b, centroids[i].0, centroids[i].1, centroids[i].2) > 1 {
-                changed = true;
-                centroids[i] = (new_r, new_g, new_b);
-            }
-        }
+    hash_rgba_export(
+        image_data,
+        width,
+        height,
+        hash_size,
+        |data, width, height, hash_size| {
+            calculate_composite_hash_for_rgba(data, width, height, hash_size, &options)
+        },
+    )
+}
 
-        if !changed {
-            break;
-        }
+/// `calculate_composite_hash` over encoded image bytes.
+#[no_mangle]
+pub extern "C" fn calculate_composite_hash_from_encoded(
+    data: *const u8,
+    len: i32,
+    hash_size: i32,
+    options: *const i32,
+) -> *mut HashResult {
+    let Some(bytes) = encoded_bytes(data, len) else {
+        return create_error_result("Invalid input parameters");
+    };
+
+    let image = match decode_rgba(bytes) {
+        Ok(image) => image,
+        Err(message) => return create_error_result(message),
+    };
+    calculate_composite_hash(
+        image.rgba.as_ptr(),
+        image.width as i32,
+        image.height as i32,
+        hash_size,
+        options,
+    )
+}
+
+/// Parses a composite record; `None` unless it holds valid aHash, dHash and pHash
+/// components in that order.
+fn parse_composite_hash(ptr: *const u8) -> Option<Vec<PackedHash>> {
+    let bytes = parse_c_hex(ptr)?;
+    let parts: Vec<PackedHash> = bytes
+        .split(|&b| b == COMPOSITE_SEPARATOR)
+        .map(parse_hash_bytes)
+        .collect();
+
+    let well_formed = parts.len() == COMPOSITE_ALGORITHMS.len()
+        && parts
+            .iter()
+            .zip(COMPOSITE_ALGORITHMS)
+            .all(|(part, algorithm)| part.valid && part.algorithm == algorithm as u8);
+    well_formed.then_some(parts)
+}
+
+/// Fused similarity of two composite records in [0, 1]: the weighted mean over aHash, dHash
+/// and pHash of `1 - distance / bits`. Weights must be non-negative; all zero means equal
+/// weights. Returns -1.0 for invalid records, weights, or hash sizes that differ.
+#[no_mangle]
+pub extern "C" fn compare_composite_hashes(
+    hash1: *const u8,
+    hash2: *const u8,
+    weight_ahash: f64,
+    weight_dhash: f64,
+    weight_phash: f64,
+) -> f64 {
+    let (Some(parts1), Some(parts2)) = (parse_composite_hash(hash1), parse_composite_hash(hash2))
+    else {
+        return -1.0;
+    };
+
+    let mut weights = [weight_ahash, weight_dhash, weight_phash];
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return -1.0;
+    }
+    if weights.iter().all(|&w| w == 0.0) {
+        weights = [1.0; 3];
     }
 
-    // Build output with population counts
-    let mut colors: Vec<(u32, u32, u32, u32)> = Vec::with_capacity(k);
-    for i in 0..k {
-        if cluster_counts[i] > 0 {
-            colors.push((centroids[i].0, centroids[i].1, centroids[i].2, cluster_counts[i] as u32));
+    let mut score = 0.0;
+    for ((part1, part2), weight) in parts1.iter().zip(&parts2).zip(weights) {
+        let distance = hamming_distance_packed(part1, part2, -1);
+        if distance < 0 || part1.bits == 0 {
+            return -1.0;
         }
+        score += weight * (1.0 - distance as f64 / part1.bits as f64);
     }
 
-    // Sort by population descending
-    colors.sort_by(|a, b| b.3.cmp(&a.3));
+    score / weights.iter().sum::<f64>()
+}
 
-    let ptr = alloc_color_array(&colors);
-    create_color_result(ptr, colors.len() as i32, 0, null_mut())
+// ===== Normalized Similarity =====
+
+/// Bit length of packed hashes, as reported by `pack_hashes` or the packed batch exports.
+/// `None` unless `bits` fits in `words` blocks; the padding bits cannot be told apart from
+/// hash bits, so the length is never guessed from the block count.
+fn packed_bit_length(words: i32, bits: i32) -> Option<usize> {
+    if words <= 0 || bits <= 0 || bits as usize > words as usize * 64 {
+        return None;
+    }
+    Some(bits as usize)
 }
 
-/// Median Cut algorithm on RGBA pixel data.
+/// Largest Hamming distance within `max_fraction` of `bits`, or `None` if the fraction is
+/// not in [0, 1]. A small epsilon keeps products like `0.1 * 640` from rounding down.
+fn fraction_to_threshold(max_fraction: f64, bits: usize) -> Option<i32> {
+    if !(0.0..=1.0).contains(&max_fraction) {
+        return None;
+    }
+    Some((max_fraction * bits as f64 + 1e-9).floor() as i32)
+}
+
+fn similarity_from_distance(distance: i32, bits: usize) -> f64 {
+    if distance < 0 || bits == 0 {
+        return -1.0;
+    }
+    1.0 - distance as f64 / bits as f64
+}
+
+/// Hamming threshold equivalent to `max_fraction` of a `bits`-bit hash, so one normalized
+/// setting can drive the raw-threshold exports. Returns -1 on invalid input.
 #[no_mangle]
-pub extern "C" fn median_cut_quantize(
-    pixel_data: *const u8,
-    width: i32,
-    height: i32,
-    num_colors: i32,
-    skip_alpha_threshold: u8,
-) -> *mut ColorResult {
-    if pixel_data.is_null() || width <= 0 || height <= 0 || num_colors <= 0 {
-        return create_color_error("Invalid input parameters");
+pub extern "C" fn normalized_threshold(max_fraction: f64, bits: i32) -> i32 {
+    if bits <= 0 {
+        return -1;
     }
+    fraction_to_threshold(max_fraction, bits as usize).unwrap_or(-1)
+}
 
-    let w = width as usize;
-    let h = height as usize;
+/// Similarity of two hash strings in [0, 1] (`1 - distance / bits`, with the bit length
+/// taken from the hashes), or -1.0 if they cannot be compared.
+#[no_mangle]
+pub extern "C" fn calculate_similarity(hash1: *const u8, hash2: *const u8) -> f64 {
+    let packed1 = parse_packed_hash(hash1);
+    let packed2 = parse_packed_hash(hash2);
+    similarity_from_distance(
+        hamming_distance_packed(&packed1, &packed2, -1),
+        packed1.bits,
+    )
+}
 
-    let Some(num_pixels) = w.checked_mul(h) else {
-        return create_color_error("Pixel count overflow");
-    };
-    let Some(data_len) = num_pixels.checked_mul(4) else {
-        return create_color_error("Data size overflow");
+/// `calculate_similarity` for packed hashes of `bits` bits (the `out_bits` of
+/// `pack_hashes` or the packed batch exports). Returns -1.0 if `bits` is not in
+/// `1..=words_per_hash * 64`.
+#[no_mangle]
+pub extern "C" fn calculate_similarity_packed(
+    hash1: *const u64,
+    hash2: *const u64,
+    words_per_hash: i32,
+    bits: i32,
+) -> f64 {
+    let Some(bit_length) = packed_bit_length(words_per_hash, bits) else {
+        return -1.0;
     };
+    let distance = calculate_hamming_distance_packed(hash1, hash2, words_per_hash);
+    similarity_from_distance(distance, bit_length)
+}
 
-    let data = unsafe { core::slice::from_raw_parts(pixel_data, data_len) };
-    let pixels = extract_rgb_pixels(data, skip_alpha_threshold);
+fn find_similar_pairs_normalized_impl(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    max_fraction: f64,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
 
-    if pixels.is_empty() {
-        return create_color_result(null_mut(), 0, 0, null_mut());
+    if hashes.is_null() || num_hashes <= 1 || fraction_to_threshold(max_fraction, 0).is_none() {
+        return null_mut();
     }
 
-    // Calculate depth from num_colors: depth = ceil(log2(num_colors))
-    let target = num_colors as usize;
-    let depth = if target <= 1 { 0 } else { (target as f64).log2().ceil() as usize };
+    let parsed_hashes = parse_hash_array(hashes, num_hashes as usize);
+    let pairs = collect_similar_pairs_mih_parsed(&parsed_hashes, |bits| {
+        fraction_to_threshold(max_fraction, bits).unwrap_or(-1)
+    });
+    matches_output(&pairs, with_distances, out_count)
+}
 
-    let result_pixels = median_cut_impl(pixels, depth);
+/// Exact pair search like `find_similar_pairs_mih`, with the threshold given as a fraction
+/// of each hash's bit length so quality settings with different hash sizes dedupe alike.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_normalized(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    max_fraction: f64,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_normalized_impl(hashes, num_hashes, max_fraction, false, out_count)
+}
 
-    let colors: Vec<(u32, u32, u32, u32)> = result_pixels
-        .into_iter()
-        .map(|(r, g, b, pop)| (r, g, b, pop))
-        .collect();
+/// `find_similar_pairs_normalized` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_normalized_with_distances(
+    hashes: *const *const u8,
+    num_hashes: i32,
+    max_fraction: f64,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_normalized_impl(hashes, num_hashes, max_fraction, true, out_count)
+}
 
-    // Sort by population descending
-    let mut colors = colors;
-    colors.sort_by(|a, b| b.3.cmp(&a.3));
+fn find_similar_pairs_normalized_packed_impl(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bits: i32,
+    max_fraction: f64,
+    with_distances: bool,
+    out_count: *mut i32,
+) -> *mut i32 {
+    write_out_i32(out_count, 0);
 
-    let ptr = alloc_color_array(&colors);
-    create_color_result(ptr, colors.len() as i32, 0, null_mut())
+    let Some(bit_length) = packed_bit_length(words_per_hash, bits) else {
+        return null_mut();
+    };
+    let Some(threshold) = fraction_to_threshold(max_fraction, bit_length) else {
+        return null_mut();
+    };
+
+    find_similar_pairs_mih_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        threshold,
+        with_distances,
+        out_count,
+    )
 }
 
-fn median_cut_impl(
-    mut pixels: Vec<(u32, u32, u32)>,
-    depth: usize,
-) -> Vec<(u32, u32, u32, u32)> {
-    if depth == 0 || pixels.is_empty() {
-        if pixels.is_empty() {
-            return Vec::new();
+/// `find_similar_pairs_normalized` over packed hashes; `bits` as in
+/// `calculate_similarity_packed`. Returns null if `bits` is out of range.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_normalized_packed(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bits: i32,
+    max_fraction: f64,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_normalized_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        bits,
+        max_fraction,
+        false,
+        out_count,
+    )
+}
+
+/// `find_similar_pairs_normalized_packed` returning `[i, j, distance]` triples.
+#[no_mangle]
+pub extern "C" fn find_similar_pairs_normalized_packed_with_distances(
+    blocks: *const u64,
+    num_hashes: i32,
+    words_per_hash: i32,
+    bits: i32,
+    max_fraction: f64,
+    out_count: *mut i32,
+) -> *mut i32 {
+    find_similar_pairs_normalized_packed_impl(
+        blocks,
+        num_hashes,
+        words_per_hash,
+        bits,
+        max_fraction,
+        true,
+        out_count,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift64 so failures reproduce without a rand dependency.
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
         }
-        // Return average color with population
-        let count = pixels.len() as u64;
-        let (sr, sg, sb) = pixels.iter().fold((0u64, 0u64, 0u64), |(sr, sg, sb), &(r, g, b)| {
-            (sr + r as u64, sg + g as u64, sb + b as u64)
-        });
-        return vec![((sr / count) as u32, (sg / count) as u32, (sb / count) as u32, count as u32)];
     }
 
-    // Find the channel with the largest range
-    let (mut min_r, mut max_r) = (255u32, 0u32);
-    let (mut min_g, mut max_g) = (255u32, 0u32);
-    let (mut min_b, mut max_b) = (255u32, 0u32);
+    /// `n` hashes of `bits` bits in `words` blocks (padding bits zero): clusters of
+    /// near-duplicates around random seeds, each copy with up to `max_flips` bits flipped.
+    fn near_duplicate_blocks(
+        rng: &mut TestRng,
+        n: usize,
+        words: usize,
+        bits: usize,
+        max_flips: usize,
+    ) -> Vec<u64> {
+        let mut data = Vec::with_capacity(n * words);
+        let mut seed = vec![0u64; words];
+        for i in 0..n {
+            if i % 6 == 0 {
+                seed.iter_mut().for_each(|block| *block = rng.next());
+            }
+            let mut hash = seed.clone();
+            for _ in 0..rng.below(max_flips + 1) {
+                let bit = rng.below(bits);
+                hash[bit / 64] ^= 1u64 << (63 - bit % 64);
+            }
+            for (w, block) in hash.iter_mut().enumerate() {
+                let used = bits.saturating_sub(w * 64).min(64);
+                if used < 64 {
+                    *block &= !(u64::MAX >> used);
+                }
+            }
+            data.extend_from_slice(&hash);
+        }
+        data
+    }
 
-    for &(r, g, b) in &pixels {
-        if r < min_r { min_r = r; }
-        if r > max_r { max_r = r; }
-        if g < min_g { min_g = g; }
-        if g > max_g { max_g = g; }
-        if b < min_b { min_b = b; }
-        if b > max_b { max_b = b; }
+    #[test]
+    fn mih_matches_linear_scan() {
+        let mut rng = TestRng(0x9e37_79b9_7f4a_7c15);
+        for (words, bits) in [(1, 64), (2, 128), (3, 144), (4, 256), (16, 1024)] {
+            let data = near_duplicate_blocks(&mut rng, 300, words, bits, 16);
+            let hashes = HashBlocks { data: &data, words };
+            for threshold in [0, 1, 3, 6, 10, 16, 24, 40] {
+                let expected =
+                    collect_similar_pairs(hashes.len(), threshold, |i, j, early_stop| {
+                        hamming_distance_words(hashes.get(i), hashes.get(j), early_stop)
+                    });
+                assert_eq!(
+                    collect_similar_pairs_mih(hashes, threshold),
+                    expected,
+                    "words={words} bits={bits} threshold={threshold}"
+                );
+            }
+        }
     }
 
-    let range_r = max_r - min_r;
-    let range_g = max_g - min_g;
-    let range_b = max_b - min_b;
+    fn tag(text: &str) -> HashTag {
+        parse_hash_tag(text.as_bytes()).expect("valid tag")
+    }
 
-    // Sort by the channel with the largest range
-    if range_r >= range_g && range_r >= range_b {
-        pixels.sort_unstable_by_key(|&(r, _, _)| r);
-    } else if range_g >= range_r && range_g >= range_b {
-        pixels.sort_unstable_by_key(|&(_, g, _)| g);
-    } else {
-        pixels.sort_unstable_by_key(|&(_, _, b)| b);
+    fn build_tree(data: &[u64], words: usize) -> BkTree {
+        let hashes = HashBlocks { data, words };
+        let mut tree = BkTree::new(words);
+        for i in 0..hashes.len() {
+            tree.insert(hashes.get(i));
+        }
+        tree
     }
 
-    let mid = pixels.len() / 2;
-    let right = pixels.split_off(mid);
+    #[test]
+    fn bktree_round_trip() {
+        let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+        let words = 2;
+        let data = near_duplicate_blocks(&mut rng, 200, words, 128, 12);
+        let mut tree = build_tree(&data, words);
+        for index in [3, 50, 51, 199] {
+            assert!(tree.remove(index));
+        }
 
-    let mut results = median_cut_impl(pixels, depth - 1);
-    results.extend(median_cut_impl(right, depth - 1));
-    results
-}
+        let dhash = tag("v1:dhash:128");
+        let bytes = tree.serialize(&dhash);
+        let loaded = BkTree::deserialize(&bytes, &dhash).expect("serialized tree loads");
+        assert_eq!(loaded.live, tree.live);
+        assert_eq!(loaded.removed, tree.removed);
+        assert_eq!(loaded.serialize(&dhash), bytes);
+
+        // A blob only loads for the kind of hash it was saved with.
+        for other in ["v1:phash:128", "v1:dhash+luma=bt709:128", "v1:dhash:127"] {
+            assert!(
+                BkTree::deserialize(&bytes, &tag(other)).is_none(),
+                "{other}"
+            );
+        }
 
-#[no_mangle]
-pub extern "C" fn free_color_result(result: *mut ColorResult) {
-    if result.is_null() {
-        return;
+        let hashes = HashBlocks { data: &data, words };
+        for i in (0..hashes.len()).step_by(7) {
+            for radius in [0, 4, 12, 30] {
+                assert_eq!(
+                    loaded.query(hashes.get(i), radius),
+                    tree.query(hashes.get(i), radius)
+                );
+            }
+        }
     }
 
-    unsafe {
-        let value = result.read();
-        if !value.colors_ptr.is_null() {
-            dealloc_bytes(value.colors_ptr as *mut u8);
+    #[test]
+    fn bktree_rejects_corrupted_blobs() {
+        let mut rng = TestRng(0x0123_4567_89ab_cdef);
+        let words = 1;
+        let data = near_duplicate_blocks(&mut rng, 40, words, 64, 8);
+        let ahash = tag("v1:ahash:64");
+        let bytes = build_tree(&data, words).serialize(&ahash);
+        let header_bytes = BkTree::header_bytes(ahash.text.len());
+        let node_bytes = BkTree::node_bytes(words).unwrap();
+        let edge_offset = |node: usize| header_bytes + node * node_bytes + words * 8 + 5;
+        let parent_of = |node: usize| {
+            let offset = edge_offset(node) - 4;
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        // Edge distance that no longer matches the stored hashes.
+        let mut corrupted = bytes.clone();
+        corrupted[edge_offset(5)] ^= 0x40;
+        assert!(BkTree::deserialize(&corrupted, &ahash).is_none());
+
+        // Two children of one parent sharing an edge distance: a copy of node 1's record
+        // (hash, parent and edge all consistent) in place of one of its siblings.
+        let sibling = (2..40)
+            .find(|&n| parent_of(n) == parent_of(1))
+            .expect("root has several children");
+        let mut corrupted = bytes.clone();
+        let record = |node: usize| header_bytes + node * node_bytes;
+        corrupted.copy_within(record(1)..record(2), record(sibling));
+        assert!(BkTree::deserialize(&corrupted, &ahash).is_none());
+
+        // Word counts whose record size overflows must fail cleanly rather than panic.
+        let mut corrupted = bytes.clone();
+        corrupted[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BkTree::deserialize(&corrupted, &ahash).is_none());
+
+        // A tag length running past the blob must fail cleanly too.
+        let mut corrupted = bytes.clone();
+        corrupted[16..20].copy_from_slice(&200u32.to_le_bytes());
+        assert!(BkTree::deserialize(&corrupted, &ahash).is_none());
+
+        assert!(BkTree::deserialize(&bytes[..bytes.len() - 1], &ahash).is_none());
+        assert!(BkTree::deserialize(&bytes, &ahash).is_some());
+    }
+
+    #[test]
+    fn algorithm_field_round_trips() {
+        let trims = [(-1, -1), (0, -1), (-1, 8), (16, 0)];
+        for algorithm in HashAlgorithm::ALL {
+            for alpha in AlphaMode::ALL {
+                for luma in LumaModel::ALL {
+                    for (alpha_threshold, tolerance) in trims {
+                        let options = PreprocessOptions {
+                            gray: GrayOptions::new(alpha, 0x12ab9f, luma),
+                            trim: TrimOptions {
+                                alpha_threshold,
+                                tolerance,
+                            },
+                        };
+                        let field = algorithm_field(algorithm, &options);
+                        let parsed = parse_algorithm_field(field.as_bytes());
+                        assert!(parsed == Some((algorithm, options)), "{field}");
+                    }
+                }
+            }
         }
-        if !value.error_message.is_null() {
-            dealloc_bytes(value.error_message);
+    }
+
+    #[test]
+    fn algorithm_field_rejects_non_canonical_spellings() {
+        for field in [
+            "ahash+alpha=ignore-ffffff",
+            "ahash+alpha=composite-ffffff+luma=bt709",
+            "ahash+trim=0-8+luma=bt709",
+            "ahash+alpha=composite-FFFFFF",
+            "ahash+luma=average",
+            "ahash+trim=off-off",
+            "ahash+trim=00-8",
+            "ahash+trim=0-256",
+            "ahash+luma=bt709+luma=bt709",
+            "ahash+",
+        ] {
+            assert!(parse_algorithm_field(field.as_bytes()).is_none(), "{field}");
         }
     }
 
-    dealloc_bytes(result as *mut u8);
+    /// `width x height` opaque RGBA noise.
+    fn noise_rgba(rng: &mut TestRng, width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|_| {
+                let v = rng.next();
+                [v as u8, (v >> 8) as u8, (v >> 16) as u8, 255]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dihedral_transforms_are_distinct_and_compose() {
+        let pixel = |v: u8| [v, v, v, 255];
+        let (a, b) = (pixel(1), pixel(2));
+        let row = [a, b].concat();
+        assert!(transform_rgba(&row, 2, 1, 1) == ([a, b].concat(), 1, 2));
+        assert!(transform_rgba(&row, 2, 1, 4) == ([b, a].concat(), 2, 1));
+
+        let image: Vec<u8> = (0..6).flat_map(pixel).collect();
+        let transformed: Vec<_> = (0..DIHEDRAL_TRANSFORMS)
+            .map(|t| transform_rgba(&image, 3, 2, t))
+            .collect();
+        for (t, (pixels, w, h)) in transformed.iter().enumerate() {
+            for other in &transformed[t + 1..] {
+                assert!(pixels != &other.0, "transforms {t} and a later one agree");
+            }
+            // One more clockwise quarter turn moves to the next rotation of the same parity.
+            let next = t / 4 * 4 + (t + 1) % 4;
+            assert!(transform_rgba(pixels, *w, *h, 1) == transformed[next]);
+        }
+        assert!(transformed[0] == (image, 3, 2));
+    }
+
+    #[test]
+    fn dihedral_hashes_match_transformed_images() {
+        let mut rng = TestRng(0x5eed_f00d_dead_beef);
+        let (width, height) = (24, 16);
+        let image = noise_rgba(&mut rng, width, height);
+        let options = PreprocessOptions::default();
+
+        for algorithm in HashAlgorithm::ALL {
+            let hashes = dihedral_hashes_export(
+                image.as_ptr(),
+                width as i32,
+                height as i32,
+                algorithm as i32,
+                8,
+                core::ptr::null(),
+            )
+            .unwrap_or_else(|error| panic!("{error}"));
+            for (t, hash) in hashes.iter().enumerate() {
+                let (pixels, w, h) = transform_rgba(&image, width, height, t);
+                let expected =
+                    calculate_hash_with_options_for_rgba(&pixels, w, h, algorithm, 8, &options);
+                assert!(expected.as_ref() == Ok(hash), "transform {t}");
+            }
+        }
+    }
+
+    /// Places `image` at `(x, y)` on a `width x height` canvas filled by `fill`.
+    fn pad_rgba(
+        image: &[u8],
+        image_width: usize,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        mut fill: impl FnMut() -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut canvas: Vec<u8> = (0..width * height).flat_map(|_| fill()).collect();
+        for (row, src) in image.chunks_exact(image_width * 4).enumerate() {
+            let offset = ((y + row) * width + x) * 4;
+            canvas[offset..offset + src.len()].copy_from_slice(src);
+        }
+        canvas
+    }
+
+    #[test]
+    fn trimmed_padding_does_not_change_the_hash() {
+        let mut rng = TestRng(0x7417_0b0d_e45c_a1e5);
+        let (width, height) = (20, 12);
+        let image = noise_rgba(&mut rng, width, height);
+        let (canvas, offset) = ((31, 20), (3, 5));
+        let expected = Bounds {
+            x0: 3,
+            y0: 5,
+            x1: 23,
+            y1: 17,
+        };
+
+        let transparent = pad_rgba(&image, width, offset, canvas, || {
+            let v = rng.next();
+            [
+                v as u8,
+                (v >> 8) as u8,
+                (v >> 16) as u8,
+                (v >> 24) as u8 % 8,
+            ]
+        });
+        let uniform = pad_rgba(&image, width, offset, canvas, || [40, 200, 90, 255]);
+        let cases = [
+            (
+                transparent,
+                TrimOptions {
+                    alpha_threshold: 7,
+                    tolerance: -1,
+                },
+            ),
+            (
+                uniform,
+                TrimOptions {
+                    alpha_threshold: -1,
+                    tolerance: 0,
+                },
+            ),
+        ];
+
+        for (padded, trim) in cases {
+            let bounds = content_bounds(&padded, canvas.0, canvas.1, &trim);
+            assert!(bounds == Some(expected));
+            let (trimmed, w, h) = trim_rgba(&padded, canvas.0, canvas.1, &trim);
+            assert!((&*trimmed, w, h) == (&image[..], width, height));
+
+            let options = PreprocessOptions {
+                gray: GrayOptions::default(),
+                trim,
+            };
+            for algorithm in HashAlgorithm::ALL {
+                let hash = |data: &[u8], w, h| {
+                    preprocessed_hash_bits(data, w, h, algorithm, 8, &options)
+                        .unwrap_or_else(|error| panic!("{error}"))
+                };
+                assert!(hash(&padded, canvas.0, canvas.1) == hash(&image, width, height));
+            }
+        }
+    }
+
+    #[test]
+    fn same_size_resampling_is_identity() {
+        let filters: Vec<_> = (0..).map_while(ResampleFilter::from_id).collect();
+        assert!(filters.len() == 3);
+        let mut rng = TestRng(0x1de7_171e_5a3e_512e);
+
+        for filter in filters {
+            for len in 1..=17 {
+                for (i, span) in filter.spans(len, len).iter().enumerate() {
+                    for (k, &w) in span.weights.iter().enumerate() {
+                        let target = (span.start + k == i) as u8 as f32;
+                        assert!((w - target).abs() < 1e-6, "filter {}", filter as i32);
+                    }
+                }
+            }
+
+            let image = noise_rgba(&mut rng, 13, 7);
+            assert!(resize_rgba(&image, 13, 7, 13, 7, filter) == image);
+        }
+    }
 }
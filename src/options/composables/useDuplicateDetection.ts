@@ -1,6 +1,7 @@
 import { ref, computed } from 'vue'
 
 import type { Emoji } from '@/types/type'
+import { isCurrentHash } from '@/utils/hash/hashFormat'
 
 export function useDuplicateDetection(emojiStore: any) {
   // State
@@ -106,7 +107,7 @@ export function useDuplicateDetection(emojiStore: any) {
         const emojis = group.emojis || []
         for (const emoji of emojis) {
           if (!emoji) continue
-          if (emoji.url && !emoji.perceptualHash) {
+          if (emoji.url && !isCurrentHash(emoji.perceptualHash)) {
             emojiUrls.push(emoji.url)
           }
         }
@@ -4,6 +4,7 @@
  */
 
 import type { Emoji, EmojiGroup } from '@/types/type'
+import { isCurrentHash } from '@/utils/hash/hashFormat'
 
 export interface DuplicateItem {
   emoji: Emoji
@@ -53,7 +54,8 @@ export async function findDuplicatesAcrossGroups(
           groupId: group.id,
           groupName: group.name
         })
-        if (emoji.url && !emoji.perceptualHash) {
+        // Hashes from an older format or algorithm are recomputed rather than compared
+        if (emoji.url && !isCurrentHash(emoji.perceptualHash)) {
           emojiUrls.push(emoji.url)
         }
       }
@@ -123,7 +125,7 @@ export async function findDuplicatesAcrossGroups(
     const hashItems = allEmojis
       .filter(
         (item): item is typeof item & { emoji: { perceptualHash: string } } =>
-          !!item.emoji.perceptualHash
+          isCurrentHash(item.emoji.perceptualHash)
       )
       .map(item => ({
         id: item.emoji.id,
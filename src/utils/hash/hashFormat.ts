@@ -0,0 +1,79 @@
+/**
+ * Helpers for the hash strings produced by the Rust WASM module.
+ *
+ * Current hashes are tagged as `v<version>:<algorithm>:<bits>:<hex payload>`; hashes cached
+ * before versioning are bare hex. Two hashes are only comparable when their tags match, so
+ * callers must never compare payloads across tags.
+ */
+export interface ParsedHash {
+  /** Everything before the payload (`v1:ahash:64`), or '' for bare hex hashes */
+  tag: string
+  payload: string
+}
+
+/**
+ * Tag prefix of the hashes the hash services compute today: the mean-threshold aHash of
+ * `calculate_perceptual_hash`. Stored hashes without it are stale and must be recomputed.
+ */
+export const CURRENT_HASH_PREFIX = 'v1:ahash:'
+
+export function parseHash(hash: string): ParsedHash {
+  if (!hash.startsWith('v')) {
+    return { tag: '', payload: hash }
+  }
+
+  const separator = hash.lastIndexOf(':')
+  if (separator < 0) {
+    return { tag: '', payload: hash }
+  }
+
+  return { tag: hash.slice(0, separator), payload: hash.slice(separator + 1) }
+}
+
+/** Tags a hex aHash payload of `bits` bits the way `calculate_perceptual_hash` does */
+export function tagAverageHash(payload: string, bits: number): string {
+  return `${CURRENT_HASH_PREFIX}${bits}:${payload}`
+}
+
+/** Whether a stored hash was computed with the current format and algorithm */
+export function isCurrentHash(hash: string | null | undefined): hash is string {
+  return !!hash && hash.startsWith(CURRENT_HASH_PREFIX)
+}
+
+/**
+ * Bit-level Hamming distance between two hashes, or -1 when they are not comparable
+ * (different tags or payload lengths), mirroring `calculate_hamming_distance`.
+ */
+export function hashDistance(hash1: string, hash2: string): number {
+  if (!hash1 || !hash2) return -1
+
+  const parsed1 = parseHash(hash1)
+  const parsed2 = parseHash(hash2)
+  if (parsed1.tag !== parsed2.tag) return -1
+  if (parsed1.payload.length !== parsed2.payload.length) return -1
+
+  let distance = 0
+  for (let i = 0; i < parsed1.payload.length; i++) {
+    const v1 = parseInt(parsed1.payload[i], 16)
+    const v2 = parseInt(parsed2.payload[i], 16)
+    if (Number.isNaN(v1) || Number.isNaN(v2)) return -1
+    let xor = v1 ^ v2
+    while (xor) {
+      distance += xor & 1
+      xor >>= 1
+    }
+  }
+
+  return distance
+}
+
+/**
+ * Bucket key made of the tag and the first `prefixLength` payload digits, so hashes with
+ * different tags never share a bucket and the prefix still spreads tagged hashes out.
+ * The key is itself a valid hash string with the same tag.
+ */
+export function hashBucketKey(hash: string, prefixLength: number): string {
+  const { tag, payload } = parseHash(hash)
+  const prefix = payload.substring(0, prefixLength)
+  return tag ? `${tag}:${prefix}` : prefix
+}
@@ -1,6 +1,7 @@
 import { imageCacheService } from '../imageCacheService'
 
 import { UnionFind } from './unionFind'
+import { CURRENT_HASH_PREFIX, hashBucketKey, parseHash, tagAverageHash } from './hashFormat'
 import { wasmHashService } from './wasmHashService'
 import { type HashCalculationOptions, type BatchHashResult } from './types'
 
@@ -101,7 +102,7 @@ export class OptimizedHashService {
           hexHash += parseInt(chunk, 2).toString(16)
         }
 
-        return hexHash
+        return '${CURRENT_HASH_PREFIX}' + grayData.length + ':' + hexHash
       }
     `
 
@@ -524,7 +525,8 @@ export class OptimizedHashService {
       hexHash += parseInt(chunk, 2).toString(16)
     }
 
-    return hexHash
+    // Same mean-threshold aHash as the WASM module, so tag it the same way
+    return tagAverageHash(hexHash, grayData.length)
   }
 
   private async fetchImage(url: string): Promise<Blob | null> {
@@ -639,10 +641,12 @@ export class OptimizedHashService {
   }
 
   // Public method for external access
+  // Returns Infinity for hashes that cannot be compared, so `distance <= threshold` stays false
   hammingDistance(hash1: string, hash2: string): number {
     // Use WASM if available
     if (this.wasmAvailable) {
-      return wasmHashService.calculateHammingDistance(hash1, hash2)
+      const distance = wasmHashService.calculateHammingDistance(hash1, hash2)
+      return distance < 0 ? Infinity : distance
     }
 
     // Use optimized binary popcount method
@@ -655,6 +659,7 @@ export class OptimizedHashService {
    */
   private hammingDistanceFast(hash1: string, hash2: string): number {
     if (hash1.length !== hash2.length) return Infinity
+    if (parseHash(hash1).tag !== parseHash(hash2).tag) return Infinity
 
     // Get or compute binary representations
     const bin1 = this.hexToBigInt(hash1)
@@ -666,22 +671,23 @@ export class OptimizedHashService {
   }
 
   /**
-   * Convert hex string to BigInt (cached)
+   * Convert a hash's hex payload to BigInt (cached); tagged hashes drop their tag first
    */
-  private hexToBigInt(hex: string): bigint {
-    const cached = this.binaryHashCache.get(hex)
+  private hexToBigInt(hash: string): bigint {
+    const cached = this.binaryHashCache.get(hash)
     if (cached !== undefined) {
       return cached
     }
 
     // Convert hex to binary representation
     // Each hex character represents 4 bits
+    const hex = parseHash(hash).payload
     let result = 0n
     for (let i = 0; i < hex.length; i++) {
       result = (result << 4n) | BigInt(parseInt(hex[i], 16))
     }
 
-    this.binaryHashCache.set(hex, result)
+    this.binaryHashCache.set(hash, result)
     return result
   }
 
@@ -768,14 +774,15 @@ export class OptimizedHashService {
 
     const startTime = performance.now()
 
-    // Filter valid items
-    const validItems = items.filter(item => item.hash && item.hash.length >= 2)
-
     // Sort by hash prefix and build bucket info
+    // Buckets key on the tag plus payload prefix, so tagged hashes do not all share one bucket
     const prefixLength = 2
+    const validItems = items.filter(
+      item => item.hash && parseHash(item.hash).payload.length >= prefixLength
+    )
     const sortedItems = [...validItems].sort((a, b) => {
-      const prefixA = a.hash.substring(0, prefixLength)
-      const prefixB = b.hash.substring(0, prefixLength)
+      const prefixA = hashBucketKey(a.hash, prefixLength)
+      const prefixB = hashBucketKey(b.hash, prefixLength)
       return prefixA.localeCompare(prefixB)
     })
 
@@ -788,7 +795,7 @@ export class OptimizedHashService {
     let currentStart = 0
 
     for (let i = 0; i < sortedItems.length; i++) {
-      const prefix = sortedItems[i].hash.substring(0, prefixLength)
+      const prefix = hashBucketKey(sortedItems[i].hash, prefixLength)
       if (prefix !== currentPrefix) {
         if (currentPrefix !== '') {
           bucketSizes.push(i - currentStart)
@@ -894,14 +901,15 @@ export class OptimizedHashService {
     const startTime = performance.now()
     const uf = new UnionFind()
 
-    // Group items by hash prefix for bucketing (first 2 characters = 256 buckets)
-    // Items with very different prefixes are unlikely to be similar
+    // Group items by hash prefix for bucketing (first 2 payload characters = 256 buckets
+    // per tag). Items with very different prefixes are unlikely to be similar, and keys with
+    // different tags compare as Infinity below, so incompatible hashes are never paired
     const prefixBuckets = new Map<string, T[]>()
     const prefixLength = 2
 
     for (const item of items) {
-      if (!item.hash || item.hash.length < prefixLength) continue
-      const prefix = item.hash.substring(0, prefixLength)
+      if (!item.hash || parseHash(item.hash).payload.length < prefixLength) continue
+      const prefix = hashBucketKey(item.hash, prefixLength)
       if (!prefixBuckets.has(prefix)) {
         prefixBuckets.set(prefix, [])
       }
@@ -1,4 +1,5 @@
 import { type WASMHashResult, type SimilarPair, type RustWasmExports } from './types'
+import { hashDistance, tagAverageHash } from './hashFormat'
 
 class WASMHashService {
   private exports: RustWasmExports | null = null
@@ -270,6 +271,10 @@ class WASMHashService {
     }
   }
 
+  /**
+   * Hamming distance between two hashes, or a negative value when they are not comparable
+   * (different algorithm, size or format version)
+   */
   calculateHammingDistance(hash1: string, hash2: string): number {
     if (!this.wasmAvailable || !this.exports) {
       return this.fallbackHammingDistance(hash1, hash2)
@@ -462,21 +467,8 @@ class WASMHashService {
   }
 
   private fallbackHammingDistance(hash1: string, hash2: string): number {
-    if (!hash1 || !hash2) return -1
-    if (hash1.length !== hash2.length) return -1
-
-    let distance = 0
-    for (let i = 0; i < hash1.length; i++) {
-      const v1 = parseInt(hash1[i], 16)
-      const v2 = parseInt(hash2[i], 16)
-      let xor = v1 ^ v2
-      while (xor) {
-        distance += xor & 1
-        xor >>= 1
-      }
-    }
-
-    return distance
+    // Mirror the WASM module: hashes with different tags are not comparable
+    return hashDistance(hash1, hash2)
   }
 
   private fallbackFindSimilarPairs(hashes: string[], threshold: number): SimilarPair[] {
@@ -526,7 +518,8 @@ class WASMHashService {
       hexHash += parseInt(chunk, 2).toString(16)
     }
 
-    return hexHash
+    // Same mean-threshold aHash as the WASM module, so tag it the same way
+    return tagAverageHash(hexHash, grayData.length)
   }
 
   isSupported(): boolean {
@@ -3,6 +3,8 @@
  * Optimized for perceptual hash calculation and duplicate detection
  */
 
+import { hashDistance, isCurrentHash } from './hash/hashFormat'
+
 export interface CachedImage {
   id: string
   url: string
@@ -272,7 +274,8 @@ export class ImageCacheService {
 
       request.onsuccess = () => {
         const cachedImage = request.result as CachedImage | undefined
-        if (cachedImage && cachedImage.hash) {
+        // Hashes from an older format or algorithm count as missing so they get recomputed
+        if (cachedImage && isCurrentHash(cachedImage.hash)) {
           // 异步队列更新访问统计，不阻塞读取
           this.queueStatsUpdate(id, cachedImage.accessCount)
           resolve(cachedImage.hash)
@@ -473,7 +476,7 @@ export class ImageCacheService {
         const similarUrls: string[] = []
 
         for (const cachedImage of cachedImages) {
-          if (cachedImage.hash) {
+          if (isCurrentHash(cachedImage.hash)) {
             const distance = this.hammingDistance(targetHash, cachedImage.hash)
             if (distance <= threshold) {
               similarUrls.push(cachedImage.url)
@@ -489,13 +492,9 @@ export class ImageCacheService {
   }
 
   private hammingDistance(hash1: string, hash2: string): number {
-    if (hash1.length !== hash2.length) return Infinity
-
-    let distance = 0
-    for (let i = 0; i < hash1.length; i++) {
-      if (hash1[i] !== hash2[i]) distance++
-    }
-    return distance
+    // Hashes with different tags are not comparable
+    const distance = hashDistance(hash1, hash2)
+    return distance < 0 ? Infinity : distance
   }
 
   /**